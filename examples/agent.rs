@@ -0,0 +1,39 @@
+//! `agent` runs a chat model with a Rust tool until the model answers the prompt,
+//! then prints the transcript of the run.
+extern crate prediction_guard as pg_client;
+
+use pg_client::{agent, client};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    let agent = agent::Agent::new(clt, "Hermes-3-Llama-3.1-70B".to_string())
+        .system_prompt("You answer questions about the weather.".to_string())
+        .max_iterations(5)
+        .max_tokens(500)
+        .tool(
+            "get_weather".to_string(),
+            "Returns the current weather for a city".to_string(),
+            serde_json::json!({
+                "type": "object",
+                "properties": { "city": { "type": "string" } },
+                "required": ["city"],
+            }),
+            |args: serde_json::Value| async move {
+                let city = args["city"].as_str().ok_or("missing city")?;
+                Ok(format!("It is 21 degrees and sunny in {}", city))
+            },
+        );
+
+    let run = agent
+        .run("Should I take an umbrella in Paris today?".to_string())
+        .await
+        .expect("error from agent run");
+
+    for step in &run.transcript {
+        println!("{:?}\n", step);
+    }
+
+    println!("\nagent response ({:?}):\n\n{}", run.stop_reason, run.output);
+}
@@ -2,7 +2,7 @@
 //! type [`chat::Response`]
 extern crate prediction_guard as pg_client;
 
-use pg_client::{chat, client};
+use pg_client::{chat, client, models};
 
 #[tokio::main]
 async fn main() {
@@ -10,7 +10,7 @@ async fn main() {
 
     // Load the list of models available for chat completion.
     let models = clt
-        .retrieve_model_list("chat-completion".to_string())
+        .retrieve_model_list(models::Capability::ChatCompletion)
         .await
         .expect("model list");
 
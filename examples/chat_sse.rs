@@ -5,7 +5,7 @@ extern crate prediction_guard as pg_client;
 
 use std::io::Write;
 
-use pg_client::{chat, client};
+use pg_client::{chat, client, models};
 
 #[tokio::main]
 async fn main() {
@@ -13,7 +13,7 @@ async fn main() {
 
     // Load the list of models available for chat completion.
     let models = clt
-        .retrieve_model_list("chat-completions".to_string())
+        .retrieve_model_list(models::Capability::ChatCompletion)
         .await
         .expect("model list");
 
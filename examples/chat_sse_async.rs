@@ -3,7 +3,7 @@
 //! allowing for asynchronous processing of the event.
 extern crate prediction_guard as pg_client;
 
-use pg_client::{chat, client};
+use pg_client::{chat, client, models};
 use std::io::Write;
 use tokio::sync::mpsc;
 
@@ -13,7 +13,7 @@ async fn main() {
 
     // Load the list of models available for chat completion.
     let models = clt
-        .retrieve_model_list("chat-completion".to_string())
+        .retrieve_model_list(models::Capability::ChatCompletion)
         .await
         .expect("model list");
 
@@ -44,19 +44,12 @@ async fn main() {
     let lock = std::io::stdout().lock();
     let mut buf = std::io::BufWriter::new(lock);
 
-    loop {
-        match rx.recv().await {
-            Some(msg) => {
-                if msg == "STOP".to_string() {
-                    break;
-                }
-
-                let _ = buf.write(msg.as_bytes());
-                let _ = buf.flush();
-            }
-            None => {
-                break;
-            }
+    while let Some(msg) = rx.recv().await {
+        if msg == "stop" {
+            break;
         }
+
+        let _ = buf.write(msg.as_bytes());
+        let _ = buf.flush();
     }
 }
@@ -0,0 +1,55 @@
+//! `chat_stream` sends a prompt to Prediction Guard and returns the response as a
+//! stream of [`chat::ResponseEvents`]. The stream can be combined with the adapters
+//! from `futures::StreamExt`, here a timeout is applied to every event.
+extern crate prediction_guard as pg_client;
+
+use std::io::Write;
+use std::time::Duration;
+
+use futures::StreamExt;
+use pg_client::{chat, client, models};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    // Load the list of models available for chat completion.
+    let models = clt
+        .retrieve_model_list(models::Capability::ChatCompletion)
+        .await
+        .expect("model list");
+
+    assert!(!models.is_empty());
+
+    let req = chat::Request::<chat::Message>::new(models[0].clone())
+        .add_message(
+            chat::Roles::User,
+            "How do you feel about the world in general".to_string(),
+        )
+        .max_tokens(300)
+        .temperature(0.1);
+
+    let mut stream = clt
+        .generate_chat_completion_stream(&req)
+        .await
+        .expect("error from chat stream");
+
+    let lock = std::io::stdout().lock();
+    let mut buf = std::io::BufWriter::new(lock);
+
+    loop {
+        let evt = match tokio::time::timeout(Duration::from_secs(30), stream.next()).await {
+            Ok(Some(evt)) => evt.expect("error from chat stream event"),
+            Ok(None) => break,
+            Err(_) => {
+                println!("\n\ntimed out waiting for the next event");
+                break;
+            }
+        };
+
+        if let Some(choice) = evt.choices.first() {
+            let _ = buf.write(choice.delta.content.as_bytes());
+            let _ = buf.flush();
+        }
+    }
+}
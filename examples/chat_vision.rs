@@ -3,14 +3,14 @@
 extern crate prediction_guard as pg_client;
 
 use pg_client::chat::MessageVision;
-use pg_client::{chat, client};
+use pg_client::{chat, client, models};
 
 #[tokio::main]
 async fn main() {
     let clt = client::Client::new().expect("client value");
 
     // Load the list of models available for chat vision.
-    let models = clt.retrieve_model_list("chat-with-image".to_string()).await.expect("model list");
+    let models = clt.retrieve_model_list(models::Capability::ChatWithImage).await.expect("model list");
 
     assert!(!models.is_empty());
 
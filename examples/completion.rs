@@ -2,14 +2,14 @@
 //! type [`completion::Response`].
 extern crate prediction_guard as pg_client;
 
-use pg_client::{client, completion};
+use pg_client::{client, completion, models};
 
 #[tokio::main]
 async fn main() {
     let clt = client::Client::new().expect("client value");
 
     // Load the list of models available for completion.
-    let models = clt.retrieve_model_list("completion".to_string()).await.expect("model list");
+    let models = clt.retrieve_model_list(models::Capability::Completion).await.expect("model list");
 
     assert!(!models.is_empty());
 
@@ -0,0 +1,40 @@
+//! `completion_stream` sends a prompt to Prediction Guard and prints the completion
+//! as it is generated, from the stream of [`completion::ResponseEvents`].
+extern crate prediction_guard as pg_client;
+
+use std::io::Write;
+
+use futures::TryStreamExt;
+use pg_client::{client, completion, models};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    // Load the list of models available for completion.
+    let models = clt.retrieve_model_list(models::Capability::Completion).await.expect("model list");
+
+    assert!(!models.is_empty());
+
+    let req = completion::Request::new(
+        models[0].clone(),
+        "fn fibonacci(n: u64) -> u64 {".to_string(),
+    )
+    .max_tokens(300)
+    .temperature(0.1);
+
+    let mut stream = clt
+        .generate_completion_stream(&req)
+        .await
+        .expect("error from completion stream");
+
+    let lock = std::io::stdout().lock();
+    let mut buf = std::io::BufWriter::new(lock);
+
+    while let Some(evt) = stream.try_next().await.expect("error from completion stream event") {
+        if let Some(choice) = evt.choices.first() {
+            let _ = buf.write(choice.text.as_bytes());
+            let _ = buf.flush();
+        }
+    }
+}
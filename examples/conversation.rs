@@ -0,0 +1,40 @@
+//! `conversation` keeps the history of a chat with a model, truncating the oldest turns
+//! with a summary when the conversation grows past the context length.
+extern crate prediction_guard as pg_client;
+
+use pg_client::{client, conversation};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    let mut conv = conversation::Conversation::new(clt, "Hermes-3-Llama-3.1-70B".to_string())
+        .system_prompt("You are a helpful travel assistant.".to_string())
+        .max_tokens(500)
+        .truncation(conversation::Truncation::Summarize);
+
+    let prompts = [
+        "I am planning a trip to France, which city should I visit first?",
+        "What should I see there?",
+        "And where should I go next?",
+    ];
+
+    for prompt in prompts {
+        let resp = conv
+            .send(prompt.to_string())
+            .await
+            .expect("error from conversation");
+
+        println!("\n\nuser: {}\n", prompt);
+
+        if let Some(choice) = resp.choices.first() {
+            println!("assistant: {}\n", choice.message.content);
+        }
+    }
+
+    println!(
+        "\n\n{} turns, {} tokens\n",
+        conv.turns(),
+        conv.token_count().await.expect("token count")
+    );
+}
@@ -3,23 +3,24 @@
 extern crate prediction_guard as pg_client;
 
 use pg_client::embedding::Direction;
-use pg_client::{client, embedding, image};
+use pg_client::{client, embedding, image, models};
 
 #[tokio::main]
 async fn main() {
-    let img_str = match image::encode(
-        "https://farm4.staticflickr.com/3300/3497460990_11dfb95dd1_z.jpg".to_string(),
-    )
-    .await
-    {
-        Ok(s) => Some(s),
-        Err(_) => None,
-    };
-
     let clt = client::Client::new().expect("client value");
 
+    // Download the image with the settings of the client and base64 encode it.
+    let img_str = clt
+        .download_image(
+            "https://farm4.staticflickr.com/3300/3497460990_11dfb95dd1_z.jpg".to_string(),
+            image::DEFAULT_MAX_SIZE,
+        )
+        .await
+        .map(|img| img.to_base64())
+        .ok();
+
     // Load the list of models available for completion.
-    let models = clt.retrieve_model_list("embedding".to_string()).await.expect("model list");
+    let models = clt.retrieve_model_list(models::Capability::Embedding).await.expect("model list");
 
     assert!(!models.is_empty());
 
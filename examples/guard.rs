@@ -0,0 +1,64 @@
+//! `guard` checks a prompt for injections and PII before it is sent to a model, and
+//! checks the output of the model for toxicity and factuality.
+extern crate prediction_guard as pg_client;
+
+use pg_client::{chat, client, guard, pii};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    let reference =
+        "The Eiffel Tower is a wrought-iron lattice tower in Paris, France. It is 330 metres tall.";
+
+    let guard = guard::Guard::new(clt.clone())
+        .injection(0.5)
+        .pii(pii::InputMethod::Replace, pii::ReplaceMethod::Category)
+        .toxicity(0.7)
+        .factuality(vec![reference.to_string()], 0.5);
+
+    let prompt =
+        "My name is John Doe and my email is john@example.com. How tall is the Eiffel Tower?";
+
+    let report = guard
+        .check_prompt(prompt.to_string())
+        .await
+        .expect("error from prompt checks");
+
+    println!("\n\nprompt checks:\n{:?}\n\n", report);
+
+    let prompt = match report.verdict {
+        guard::Verdict::Allowed => prompt.to_string(),
+        guard::Verdict::Redacted(prompt) => prompt,
+        guard::Verdict::Blocked { reason, score } => {
+            println!("prompt blocked by the {} check, score {:?}", reason, score);
+            return;
+        }
+    };
+
+    let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+        .add_message(chat::Roles::User, prompt)
+        .max_tokens(200);
+
+    let result = clt
+        .generate_chat_completion(&req)
+        .await
+        .expect("error from generate chat completion");
+
+    let output = result
+        .choices
+        .first()
+        .map(|c| c.message.content.text().into_owned())
+        .unwrap_or_default();
+
+    let report = guard
+        .check_output(output.clone())
+        .await
+        .expect("error from output checks");
+
+    println!("\n\noutput checks:\n{:?}\n\n", report);
+
+    if !report.verdict.is_blocked() {
+        println!("{}", output);
+    }
+}
@@ -19,7 +19,7 @@ async fn main() {
 
     // Models request will return only models for that capability if set
     let req = models::Request::new(
-        Some("chat-completion".to_string()),
+        Some(models::Capability::ChatCompletion),
     );
 
     let result = clt.models(Some(&req)).await.expect("error from chat-completion models");
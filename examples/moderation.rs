@@ -0,0 +1,49 @@
+//! `moderation` streams a chat completion and checks each sentence for toxicity and
+//! PII before it is printed.
+extern crate prediction_guard as pg_client;
+
+use std::io::Write;
+
+use futures::StreamExt;
+use pg_client::{chat, client, moderation, pii, PgError};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    let moderator = moderation::Moderator::new(clt)
+        .toxicity(0.7)
+        .pii(pii::ReplaceMethod::Category)
+        .action(moderation::Action::Redact);
+
+    let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+        .add_message(
+            chat::Roles::User,
+            "Write a short story about a grumpy pirate.".to_string(),
+        )
+        .max_tokens(300);
+
+    let mut stream = moderator
+        .generate_chat_completion_stream(&req)
+        .await
+        .expect("error from moderated chat");
+
+    let mut lock = std::io::stdout().lock();
+
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(event) => {
+                if let Some(choice) = event.choices.first() {
+                    write!(lock, "{}", choice.delta.content).unwrap();
+                }
+            }
+            Err(err @ PgError::Moderation { .. }) => {
+                writeln!(lock, "\n\nstopped: {}", err).unwrap();
+                return;
+            }
+            Err(err) => panic!("error from stream: {}", err),
+        }
+    }
+
+    writeln!(lock).unwrap();
+}
@@ -0,0 +1,37 @@
+//! `pseudonymize` replaces the PII in a chat with placeholders before it is sent to
+//! Prediction Guard and restores the PII in the streamed response.
+extern crate prediction_guard as pg_client;
+
+use std::io::Write;
+
+use futures::TryStreamExt;
+use pg_client::{chat, client, pii, pseudonymize};
+
+#[tokio::main]
+async fn main() {
+    let clt = client::Client::new().expect("client value");
+
+    let mut pseudo = pseudonymize::Pseudonymizer::new(clt, pii::ReplaceMethod::Category);
+
+    let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+        .add_message(
+            chat::Roles::User,
+            "Write a short thank you note to John Doe, his email is john@example.com.".to_string(),
+        )
+        .max_tokens(300);
+
+    let mut stream = pseudo
+        .generate_chat_completion_stream(&req)
+        .await
+        .expect("error from pseudonymized chat");
+
+    let mut lock = std::io::stdout().lock();
+
+    while let Some(event) = stream.try_next().await.expect("error from stream") {
+        if let Some(choice) = event.choices.first() {
+            write!(lock, "{}", choice.delta.content).unwrap();
+        }
+    }
+
+    writeln!(lock, "\n\nvault: {:?}", pseudo.vault()).unwrap();
+}
@@ -0,0 +1,358 @@
+//! Agent that runs a chat model with a set of Rust tools until the model returns an answer.
+//!
+//! The agent sends the conversation to the chat completion endpoint with the registered
+//! tools. When the model responds with tool calls, the matching tools are executed and
+//! their results are added to the conversation, which is then sent to the model again.
+//! The loop ends when the model responds without tool calls or after the maximum number
+//! of iterations.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{agent::Agent, client};
+//!
+//! let clt = client::Client::new()?;
+//!
+//! let agent = Agent::new(clt, "Hermes-3-Llama-3.1-70B".to_string())
+//!     .system_prompt("You are a helpful assistant.".to_string())
+//!     .tool(
+//!         "get_weather".to_string(),
+//!         "Returns the current weather for a city".to_string(),
+//!         serde_json::json!({
+//!             "type": "object",
+//!             "properties": { "city": { "type": "string" } },
+//!             "required": ["city"],
+//!         }),
+//!         |args: serde_json::Value| async move {
+//!             let city = args["city"].as_str().ok_or("missing city")?;
+//!             Ok(format!("It is sunny in {}", city))
+//!         },
+//!     );
+//!
+//! let run = agent.run("What is the weather in Paris?".to_string()).await?;
+//! println!("{}", run.output);
+//! ```
+use std::{fmt, future::Future, sync::Arc};
+
+use futures::future::BoxFuture;
+
+use crate::{chat, client::Client, PgError, Result};
+
+const DEFAULT_MAX_ITERATIONS: usize = 10;
+
+type ToolHandler = Arc<
+    dyn Fn(serde_json::Value) -> BoxFuture<'static, std::result::Result<String, String>>
+        + Send
+        + Sync,
+>;
+
+/// A tool registered with the agent.
+#[derive(Clone)]
+struct RegisteredTool {
+    definition: chat::Tool,
+    handler: ToolHandler,
+}
+
+/// Runs the call-model / execute-tool loop for a chat model.
+#[derive(Clone)]
+pub struct Agent {
+    client: Client,
+    model: String,
+    system_prompt: Option<String>,
+    tools: Vec<RegisteredTool>,
+    max_iterations: usize,
+    max_tokens: Option<i64>,
+    temperature: Option<f64>,
+}
+
+impl fmt::Debug for Agent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tools: Vec<&str> = self
+            .tools
+            .iter()
+            .map(|t| t.definition.function.name.as_str())
+            .collect();
+
+        f.debug_struct("Agent")
+            .field("model", &self.model)
+            .field("system_prompt", &self.system_prompt)
+            .field("tools", &tools)
+            .field("max_iterations", &self.max_iterations)
+            .field("max_tokens", &self.max_tokens)
+            .field("temperature", &self.temperature)
+            .finish()
+    }
+}
+
+/// The reason the agent stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The model responded without calling a tool.
+    Completed,
+    /// The maximum number of iterations was reached while the model was still calling tools.
+    MaxIterations,
+}
+
+/// A single step of an agent run.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// A message returned from the model, which may contain tool calls.
+    Model {
+        message: chat::Message,
+        finish_reason: Option<chat::FinishReason>,
+    },
+    /// A tool call that was executed, with the result sent back to the model.
+    Tool {
+        call: chat::ToolCall,
+        output: String,
+        /// True if the tool failed, is unknown or the arguments could not be parsed.
+        is_error: bool,
+    },
+}
+
+/// The result of an agent run.
+#[derive(Debug, Clone)]
+pub struct Run {
+    /// The content of the last message returned from the model.
+    pub output: String,
+    /// Every step of the run, in order.
+    pub transcript: Vec<Step>,
+    /// The conversation sent to the model, including the tool results.
+    pub messages: Vec<chat::Message>,
+    /// The number of calls made to the model.
+    pub iterations: usize,
+    /// The reason the run stopped.
+    pub stop_reason: StopReason,
+}
+
+impl Agent {
+    /// Creates a new agent without tools.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the chat completion endpoint.
+    /// * `model` - The model to be used for the requests.
+    pub fn new(client: Client, model: String) -> Self {
+        Self {
+            client,
+            model,
+            system_prompt: None,
+            tools: Vec::new(),
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            max_tokens: None,
+            temperature: None,
+        }
+    }
+
+    /// Sets the system prompt that starts every run.
+    ///
+    /// ## Arguments
+    ///
+    /// * `prompt` - The system prompt.
+    pub fn system_prompt(mut self, prompt: String) -> Self {
+        self.system_prompt = Some(prompt);
+        self
+    }
+
+    /// Sets the maximum number of calls to the model in a run. The default is 10.
+    ///
+    /// ## Arguments
+    ///
+    /// * `max` - The maximum number of iterations. A value of 0 is treated as 1.
+    pub fn max_iterations(mut self, max: usize) -> Self {
+        self.max_iterations = max.max(1);
+        self
+    }
+
+    /// Sets the max tokens for every call to the model.
+    ///
+    /// ## Arguments
+    ///
+    /// * `max` - The maximum number of tokens to be returned in a response.
+    pub fn max_tokens(mut self, max: i64) -> Self {
+        self.max_tokens = Some(max);
+        self
+    }
+
+    /// Sets the temperature for every call to the model.
+    ///
+    /// ## Arguments
+    ///
+    /// * `temp` - The temperature setting for the requests. Used to control randomness.
+    pub fn temperature(mut self, temp: f64) -> Self {
+        self.temperature = Some(temp);
+        self
+    }
+
+    /// Registers a tool the model may call. A tool registered with the name of an existing
+    /// tool replaces it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - The name of the tool.
+    /// * `description` - Describes what the tool does, used by the model to choose the tool.
+    /// * `parameters` - The JSON schema of the tool arguments.
+    /// * `handler` - Async function called with the parsed arguments. The returned text, or
+    ///   the error message, is sent back to the model.
+    pub fn tool<F, Fut>(
+        mut self,
+        name: String,
+        description: String,
+        parameters: serde_json::Value,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(serde_json::Value) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = std::result::Result<String, String>> + Send + 'static,
+    {
+        self.tools.retain(|t| t.definition.function.name != name);
+
+        let handler: ToolHandler = Arc::new(move |args| Box::pin(handler(args)));
+
+        self.tools.push(RegisteredTool {
+            definition: chat::Tool::function(name, description, parameters),
+            handler,
+        });
+        self
+    }
+
+    /// Runs the agent for a user prompt.
+    ///
+    /// ## Arguments
+    ///
+    /// * `prompt` - The user prompt that starts the conversation.
+    ///
+    /// Returns the [`Run`] with the final output and the transcript of every step. Reaching
+    /// the maximum number of iterations is not an error, it is reported with
+    /// [`StopReason::MaxIterations`]. Errors from the API are returned as is, a response
+    /// without choices is returned as a [`PgError::Response`].
+    pub async fn run(&self, prompt: String) -> Result<Run> {
+        let mut messages = Vec::new();
+
+        if let Some(system) = &self.system_prompt {
+            messages.push(chat::Message::new(chat::Roles::System, system.clone()));
+        }
+        messages.push(chat::Message::new(chat::Roles::User, prompt));
+
+        self.run_messages(messages).await
+    }
+
+    /// Runs the agent for an existing conversation.
+    ///
+    /// ## Arguments
+    ///
+    /// * `messages` - The conversation to continue. The system prompt of the agent is not
+    ///   added.
+    pub async fn run_messages(&self, mut messages: Vec<chat::Message>) -> Result<Run> {
+        let mut transcript = Vec::new();
+        let mut iterations = 0;
+
+        loop {
+            if iterations == self.max_iterations {
+                let output = last_output(&transcript);
+                return Ok(Run {
+                    output,
+                    transcript,
+                    messages,
+                    iterations,
+                    stop_reason: StopReason::MaxIterations,
+                });
+            }
+            iterations += 1;
+
+            let resp = self
+                .client
+                .generate_chat_completion(&self.request(&messages))
+                .await?;
+
+            let Some(choice) = resp.choices.into_iter().next() else {
+                return Err(PgError::Response(format!(
+                    "response without choices from model {}",
+                    self.model
+                )));
+            };
+
+            let mut msg = choice.message;
+            msg.role = chat::Roles::Assistant;
+
+            transcript.push(Step::Model {
+                message: msg.clone(),
+                finish_reason: choice.finish_reason,
+            });
+            messages.push(msg.clone());
+
+            if msg.tool_calls.is_empty() {
+                return Ok(Run {
+                    output: msg.content.into_text(),
+                    transcript,
+                    messages,
+                    iterations,
+                    stop_reason: StopReason::Completed,
+                });
+            }
+
+            for call in msg.tool_calls {
+                let (output, is_error) = match self.call_tool(&call).await {
+                    Ok(x) => (x, false),
+                    Err(e) => (format!("error: {}", e), true),
+                };
+
+                messages.push(chat::Message {
+                    role: chat::Roles::Tool,
+                    content: output.clone().into(),
+                    tool_call_id: Some(call.id.clone()),
+                    ..Default::default()
+                });
+                transcript.push(Step::Tool {
+                    call,
+                    output,
+                    is_error,
+                });
+            }
+        }
+    }
+
+    fn request(&self, messages: &[chat::Message]) -> chat::Request<chat::Message> {
+        let mut req = chat::Request::<chat::Message>::new(self.model.clone())
+            .with_messages(messages.to_vec());
+
+        // Backends reject an empty list of tools.
+        if !self.tools.is_empty() {
+            req = req.tools(self.tools.iter().map(|t| t.definition.clone()).collect());
+        }
+        if let Some(max) = self.max_tokens {
+            req = req.max_tokens(max);
+        }
+        if let Some(temp) = self.temperature {
+            req = req.temperature(temp);
+        }
+
+        req
+    }
+
+    async fn call_tool(&self, call: &chat::ToolCall) -> std::result::Result<String, String> {
+        let tool = self
+            .tools
+            .iter()
+            .find(|t| t.definition.function.name == call.function.name)
+            .ok_or_else(|| format!("unknown tool {}", call.function.name))?;
+
+        let args = call
+            .function
+            .parse_arguments::<serde_json::Value>()
+            .map_err(|e| format!("invalid arguments for {}, {}", call.function.name, e))?;
+
+        (tool.handler)(args).await
+    }
+}
+
+fn last_output(transcript: &[Step]) -> String {
+    transcript
+        .iter()
+        .rev()
+        .find_map(|s| match s {
+            Step::Model { message, .. } => Some(message.content.text().into_owned()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
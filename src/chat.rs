@@ -1,16 +1,22 @@
 //! Data types that are used for the chat endpoints, including chat completions, chat vision
 //! and chat events.
-use serde::{self, Deserialize, Serialize};
-use crate::pii;
+use std::borrow::Cow;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use futures::{Stream, TryStreamExt};
+use serde::{self, de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use crate::{pii, Result};
 
 /// Path to the completions chat endpoint.
 pub const PATH: &str = "/chat/completions";
 
 const IMAGE_URL_TYPE: &str = "image_url";
 const TEXT_TYPE: &str = "text";
+const FUNCTION_TYPE: &str = "function";
 
 /// Allows to request PII check and Injection check on the inputs in the chat request.
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub struct RequestInput {
     block_prompt_injection: bool,
     pii: Option<pii::InputMethod>,
@@ -18,47 +24,269 @@ pub struct RequestInput {
 }
 
 /// Allows for checking the output of the request for factuality and toxicity.
-#[derive(Debug, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, Deserialize, Serialize, Default)]
 pub(crate) struct RequestOutput {
     factuality: bool,
     toxicity: bool,
 }
 
-/// Holds a data uri which contains a base64 encoded image.
-#[derive(Serialize, Default, Clone, Deserialize, Debug)]
-struct ImageURL {
-    // Currently only base64 encoded image works.
-    url: String,
+/// Holds the URL of an image, usually a data URI which contains a base64 encoded image.
+#[derive(Serialize, Default, Clone, PartialEq, Deserialize, Debug)]
+pub struct ImageURL {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ImageDetail>,
 }
 
-/// Contains the content to use for chat vision. A prompt and an image are specified.
-#[derive(Serialize, Default, Clone, Deserialize, Debug)]
+/// The level of detail the model uses to process an image.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageDetail {
+    Auto,
+    Low,
+    High,
+}
+
+/// A part of the content of a message, either text or an image.
+#[derive(Serialize, Default, Clone, PartialEq, Deserialize, Debug)]
 pub struct Content {
     #[serde(rename = "type")]
-    content_type: String,
-    text: Option<String>,
-    image_url: Option<ImageURL>,
+    pub content_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<ImageURL>,
+}
+
+impl Content {
+    /// Creates a text part.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text of the part.
+    pub fn text(text: String) -> Self {
+        Self {
+            content_type: TEXT_TYPE.to_string(),
+            text: Some(text),
+            image_url: None,
+        }
+    }
+
+    /// Creates an image part.
+    ///
+    /// ## Arguments
+    ///
+    /// * `url` - The image URL or data URI for a base64 encoded image e.g. data:image/jpeg;base64,data
+    pub fn image_url(url: String) -> Self {
+        Self {
+            content_type: IMAGE_URL_TYPE.to_string(),
+            text: None,
+            image_url: Some(ImageURL { url, detail: None }),
+        }
+    }
+
+    /// Creates an image part with the level of detail used to process the image.
+    ///
+    /// ## Arguments
+    ///
+    /// * `url` - The image URL or data URI for a base64 encoded image.
+    /// * `detail` - The level of detail used to process the image.
+    pub fn image_url_with_detail(url: String, detail: ImageDetail) -> Self {
+        Self {
+            content_type: IMAGE_URL_TYPE.to_string(),
+            text: None,
+            image_url: Some(ImageURL {
+                url,
+                detail: Some(detail),
+            }),
+        }
+    }
+
+    /// Returns true if the part is text.
+    pub fn is_text(&self) -> bool {
+        self.content_type == TEXT_TYPE
+    }
+
+    /// Returns true if the part is an image.
+    pub fn is_image(&self) -> bool {
+        self.content_type == IMAGE_URL_TYPE
+    }
+}
+
+/// The content of a message, either plain text or an ordered list of text and image parts.
+#[derive(Serialize, Clone, PartialEq, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum MessageContent {
+    Text(String),
+    Parts(Vec<Content>),
+}
+
+impl Default for MessageContent {
+    fn default() -> Self {
+        MessageContent::Text(String::new())
+    }
+}
+
+impl MessageContent {
+    /// Returns the content if it is plain text.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            MessageContent::Text(s) => Some(s),
+            MessageContent::Parts(_) => None,
+        }
+    }
+
+    /// Returns the text of the content. The text parts are joined with a line feed and
+    /// the image parts are skipped.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            MessageContent::Text(s) => Cow::Borrowed(s),
+            MessageContent::Parts(parts) => Cow::Owned(
+                parts
+                    .iter()
+                    .filter_map(|p| p.text.as_deref())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    /// Returns the text of the content, see [`MessageContent::text`].
+    pub fn into_text(self) -> String {
+        match self {
+            MessageContent::Text(s) => s,
+            x => x.text().into_owned(),
+        }
+    }
+
+    /// Returns the parts of the content. Plain text is returned as a single text part.
+    pub fn parts(&self) -> Vec<Content> {
+        match self {
+            MessageContent::Text(s) => vec![Content::text(s.clone())],
+            MessageContent::Parts(parts) => parts.clone(),
+        }
+    }
+
+    /// Returns the number of image parts.
+    pub fn image_count(&self) -> usize {
+        match self {
+            MessageContent::Text(_) => 0,
+            MessageContent::Parts(parts) => parts.iter().filter(|p| p.is_image()).count(),
+        }
+    }
+
+    /// Returns true if there is no text and no image.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            MessageContent::Text(s) => s.is_empty(),
+            MessageContent::Parts(parts) => parts
+                .iter()
+                .all(|p| p.text.as_deref().unwrap_or_default().is_empty() && p.image_url.is_none()),
+        }
+    }
+
+    /// Appends text to the content. For content with parts the text is appended to the
+    /// last part if it is text, otherwise a text part is added.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text to append.
+    pub fn push_str(&mut self, text: &str) {
+        match self {
+            MessageContent::Text(s) => s.push_str(text),
+            MessageContent::Parts(parts) => match parts.last_mut() {
+                Some(Content {
+                    text: Some(last), ..
+                }) => last.push_str(text),
+                _ => parts.push(Content::text(text.to_string())),
+            },
+        }
+    }
+}
+
+impl fmt::Display for MessageContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(s: String) -> Self {
+        MessageContent::Text(s)
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(s: &str) -> Self {
+        MessageContent::Text(s.to_string())
+    }
+}
+
+impl From<Vec<Content>> for MessageContent {
+    fn from(parts: Vec<Content>) -> Self {
+        MessageContent::Parts(parts)
+    }
+}
+
+impl PartialEq<str> for MessageContent {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == Some(other)
+    }
+}
+
+impl PartialEq<&str> for MessageContent {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == Some(*other)
+    }
+}
+
+impl PartialEq<String> for MessageContent {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == Some(other.as_str())
+    }
 }
 
 /// Message used when calling chat vision.
-#[derive(Serialize, Default, Deserialize, Debug)]
+#[derive(Serialize, Default, Clone, Deserialize, Debug)]
 pub struct MessageVision {
     role: Roles,
     content: Vec<Content>,
 }
 
 /// Used to send a request for chat.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct Request<T> {
     pub(crate) model: String,
-    messages: Vec<T>,
-    max_tokens: i64,
+    pub(crate) messages: Vec<T>,
+    pub(crate) max_tokens: i64,
     temperature: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     top_k: Option<i64>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repetition_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_logprobs: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     input: Option<RequestInput>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) output: Option<RequestOutput>,
@@ -79,21 +307,9 @@ impl Request<MessageVision> {
         prompt: String,
         image_uri: String,
     ) -> Request<MessageVision> {
-        let img_content = Content {
-            content_type: IMAGE_URL_TYPE.to_string(),
-            image_url: Some(ImageURL { url: image_uri }),
-            ..Default::default()
-        };
-
-        let txt_content = Content {
-            content_type: TEXT_TYPE.to_string(),
-            text: Some(prompt),
-            ..Default::default()
-        };
-
         self.messages.push(MessageVision {
             role,
-            content: vec![img_content, txt_content],
+            content: vec![Content::image_url(image_uri), Content::text(prompt)],
         });
 
         self
@@ -109,12 +325,34 @@ impl Request<Message> {
     /// * `role` - The role of the user sending the message.
     /// * `prompt` - The text prompt to be added to the message.
     pub fn add_message(mut self, role: Roles, prompt: String) -> Request<Message> {
-        let m = Message {
-            role,
-            content: prompt,
-        };
+        self.messages.push(Message::new(role, prompt));
+        self
+    }
+
+    /// Adds a message with text and image parts to the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `role` - The role of the user sending the message.
+    /// * `parts` - The ordered text and image parts of the message.
+    pub fn add_message_parts(mut self, role: Roles, parts: Vec<Content>) -> Request<Message> {
+        self.messages.push(Message::with_parts(role, parts));
+        self
+    }
 
-        self.messages.push(m);
+    /// Adds the result of a tool call to the request, to send it back to the model.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tool_call_id` - The id of the tool call from the assistant message.
+    /// * `content` - The result of the tool call.
+    pub fn add_tool_message(mut self, tool_call_id: String, content: String) -> Request<Message> {
+        self.messages.push(Message {
+            role: Roles::Tool,
+            content: content.into(),
+            tool_call_id: Some(tool_call_id),
+            ..Default::default()
+        });
         self
     }
 }
@@ -133,6 +371,17 @@ impl<T> Request<T> {
             temperature: 0.0,
             top_p: None,
             top_k: None,
+            stop: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            repetition_penalty: None,
+            seed: None,
+            n: None,
+            logprobs: None,
+            top_logprobs: None,
+            tools: None,
+            tool_choice: None,
+            response_format: None,
             input: None,
             output: None,
             stream: false,
@@ -201,6 +450,118 @@ impl<T> Request<T> {
         self
     }
 
+    /// Sets the stop sequences for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stop` - The sequences where the model stops generating further tokens.
+    pub fn stop(mut self, stop: Vec<String>) -> Request<T> {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Sets the presence penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Penalizes tokens that already appeared in the text, between -2.0 and 2.0.
+    pub fn presence_penalty(mut self, penalty: f64) -> Request<T> {
+        self.presence_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the frequency penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Penalizes tokens based on how often they appeared in the text, between -2.0 and 2.0.
+    pub fn frequency_penalty(mut self, penalty: f64) -> Request<T> {
+        self.frequency_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the repetition penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Multiplicative penalty for repeated tokens, 1.0 means no penalty.
+    pub fn repetition_penalty(mut self, penalty: f64) -> Request<T> {
+        self.repetition_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the seed for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `seed` - The seed used for sampling, to make the output reproducible.
+    pub fn seed(mut self, seed: i64) -> Request<T> {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the number of choices to generate for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `n` - The number of choices returned in the response.
+    pub fn n(mut self, n: i64) -> Request<T> {
+        self.n = Some(n);
+        self
+    }
+
+    /// Requests the log probabilities of the generated tokens.
+    ///
+    /// ## Arguments
+    ///
+    /// * `top_logprobs` - The number of most likely alternatives to return for every
+    ///   token, if any.
+    pub fn logprobs(mut self, top_logprobs: Option<i64>) -> Request<T> {
+        self.logprobs = Some(true);
+        self.top_logprobs = top_logprobs;
+        self
+    }
+
+    /// Sets the tools the model may call.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tools` - The tools available to the model.
+    pub fn tools(mut self, tools: Vec<Tool>) -> Request<T> {
+        self.tools = Some(tools);
+        self
+    }
+
+    /// Adds a tool the model may call.
+    ///
+    /// ## Arguments
+    ///
+    /// * `tool` - The tool to be added to the request.
+    pub fn add_tool(mut self, tool: Tool) -> Request<T> {
+        self.tools.get_or_insert_with(Vec::new).push(tool);
+        self
+    }
+
+    /// Sets how the model chooses which tool to call.
+    ///
+    /// ## Arguments
+    ///
+    /// * `choice` - Determines whether and which tool is called.
+    pub fn tool_choice(mut self, choice: ToolChoice) -> Request<T> {
+        self.tool_choice = Some(choice);
+        self
+    }
+
+    /// Sets the format of the response, to request JSON output.
+    ///
+    /// ## Arguments
+    ///
+    /// * `format` - The format the model must use for the response.
+    pub fn response_format(mut self, format: ResponseFormat) -> Request<T> {
+        self.response_format = Some(format);
+        self
+    }
+
     /// Sets the input parameters for the request, to check for prompt injection and PII.
     ///
     /// ## Arguments
@@ -267,14 +628,252 @@ impl<T> Request<T> {
 pub struct ResponseChoice {
     pub message: Message,
     pub index: i64,
+    pub finish_reason: Option<FinishReason>,
+    #[serde(deserialize_with = "deserialize_logprobs")]
+    pub logprobs: Option<Logprobs>,
 }
 
 /// Represents a message in the chat response.
-#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Message {
     pub role: Roles,
-    pub content: String,
+    #[serde(deserialize_with = "deserialize_message_content")]
+    pub content: MessageContent,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl Message {
+    /// Creates a text message.
+    ///
+    /// ## Arguments
+    ///
+    /// * `role` - The role of the user sending the message.
+    /// * `content` - The text of the message.
+    pub fn new(role: Roles, content: String) -> Self {
+        Self {
+            role,
+            content: MessageContent::Text(content),
+            ..Default::default()
+        }
+    }
+
+    /// Creates a message with text and image parts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `role` - The role of the user sending the message.
+    /// * `parts` - The ordered text and image parts of the message.
+    pub fn with_parts(role: Roles, parts: Vec<Content>) -> Self {
+        Self {
+            role,
+            content: MessageContent::Parts(parts),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<MessageVision> for Message {
+    fn from(m: MessageVision) -> Self {
+        Message::with_parts(m.role, m.content)
+    }
+}
+
+/// A tool the model may call.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Tool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: FunctionDefinition,
+}
+
+impl Tool {
+    /// Creates a function tool.
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - The name of the function.
+    /// * `description` - Describes what the function does, used by the model to choose the tool.
+    /// * `parameters` - The JSON schema of the function arguments.
+    pub fn function(name: String, description: String, parameters: serde_json::Value) -> Self {
+        Self {
+            tool_type: FUNCTION_TYPE.to_string(),
+            function: FunctionDefinition {
+                name,
+                description: Some(description),
+                parameters,
+            },
+        }
+    }
+}
+
+/// The definition of a function the model may call.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub parameters: serde_json::Value,
+}
+
+/// Controls whether and which tool the model calls.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    /// The model decides whether to call a tool.
+    Auto,
+    /// The model does not call any tool.
+    None,
+    /// The model calls at least one tool.
+    Required,
+    /// The model calls the function with the name.
+    Function(String),
+}
+
+impl Serialize for ToolChoice {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => serde_json::json!({
+                "type": FUNCTION_TYPE,
+                "function": { "name": name },
+            })
+            .serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ToolChoice {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        match value.as_str() {
+            Some("auto") => return Ok(ToolChoice::Auto),
+            Some("none") => return Ok(ToolChoice::None),
+            Some("required") => return Ok(ToolChoice::Required),
+            _ => {}
+        }
+
+        match value.pointer("/function/name").and_then(|x| x.as_str()) {
+            Some(name) => Ok(ToolChoice::Function(name.to_string())),
+            None => Err(serde::de::Error::custom(format!(
+                "invalid tool choice: {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// The format the model must use for the response.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Plain text, the default.
+    Text,
+    /// Any valid JSON object.
+    JsonObject,
+    /// JSON that matches the schema.
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+impl ResponseFormat {
+    /// Creates a format for JSON that matches the schema. Strict mode is not set, see
+    /// [`ResponseFormat::strict`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - The name of the schema.
+    /// * `schema` - The JSON schema of the response.
+    pub fn json_schema(name: String, schema: serde_json::Value) -> Self {
+        ResponseFormat::JsonSchema {
+            json_schema: JsonSchemaFormat {
+                name,
+                schema,
+                strict: None,
+            },
+        }
+    }
+
+    /// Sets strict mode for a JSON schema format, other formats are not changed. In
+    /// strict mode the schema must only use the subset of JSON schema supported by the
+    /// server, e.g. every property is required and `additionalProperties` is `false`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `strict` - Enforces the schema exactly.
+    pub fn strict(mut self, strict: bool) -> Self {
+        if let ResponseFormat::JsonSchema { json_schema } = &mut self {
+            json_schema.strict = Some(strict);
+        }
+        self
+    }
+}
+
+/// The schema of a [`ResponseFormat::JsonSchema`] response.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    pub schema: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// A call to a tool made by the model.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: FunctionCall,
+}
+
+/// The function and arguments of a tool call.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FunctionCall {
+    pub name: String,
+    /// The arguments of the call as a JSON string.
+    pub arguments: String,
+}
+
+impl FunctionCall {
+    /// Parses the arguments of the call.
+    pub fn parse_arguments<A: DeserializeOwned>(&self) -> Result<A> {
+        let args = if self.arguments.trim().is_empty() {
+            "{}"
+        } else {
+            &self.arguments
+        };
+
+        Ok(serde_json::from_str(args)?)
+    }
+}
+
+/// Part of a tool call that is streamed in a chat events response.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ToolCallDelta {
+    pub index: usize,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub tool_type: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+/// Part of the function of a tool call that is streamed in a chat events response.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 /// Reponse returned from the completion response for chat.
@@ -292,7 +891,10 @@ pub struct Response {
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct EventsDelta {
+    #[serde(deserialize_with = "deserialize_content")]
     pub content: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCallDelta>,
 }
 
 /// Represents the choices in a chat events response.
@@ -301,8 +903,9 @@ pub struct EventsDelta {
 pub struct ChoiceEvents {
     pub generated_text: Option<String>,
     pub index: i64,
-    pub logprobs: f64,
-    pub finish_reason: Option<String>,
+    #[serde(deserialize_with = "deserialize_logprobs")]
+    pub logprobs: Option<Logprobs>,
+    pub finish_reason: Option<FinishReason>,
     pub delta: EventsDelta,
 }
 
@@ -315,9 +918,324 @@ pub struct ResponseEvents {
     pub created: i64,
     pub model: String,
     pub choices: Vec<ChoiceEvents>,
+    #[serde(deserialize_with = "deserialize_error")]
     pub error: Option<String>,
 }
 
+/// The reason the model stopped generating text.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum FinishReason {
+    /// The model reached a natural stopping point or a stop sequence.
+    Stop,
+    /// The maximum number of tokens was reached.
+    Length,
+    /// The content was removed by a content filter.
+    ContentFilter,
+    /// The model called a tool.
+    ToolCalls,
+    /// A finish reason not known to this client.
+    Other(String),
+}
+
+impl FinishReason {
+    /// Returns the finish reason as sent by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ContentFilter => "content_filter",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Other(s) => s.as_str(),
+        }
+    }
+}
+
+impl From<String> for FinishReason {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "stop" => FinishReason::Stop,
+            "length" => FinishReason::Length,
+            "content_filter" => FinishReason::ContentFilter,
+            "tool_calls" => FinishReason::ToolCalls,
+            _ => FinishReason::Other(s),
+        }
+    }
+}
+
+impl From<FinishReason> for String {
+    fn from(r: FinishReason) -> Self {
+        r.as_str().to_string()
+    }
+}
+
+impl fmt::Display for FinishReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The log probabilities of the tokens generated for a choice.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Logprobs {
+    pub content: Vec<TokenLogprob>,
+}
+
+/// The log probability of a generated token and of the most likely alternatives.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+/// The log probability of one of the most likely tokens at a position.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// Reads the log probabilities of a choice. Older versions of the API send a number
+/// instead of the log probabilities, which is read as `None`.
+pub(crate) fn deserialize_logprobs<'de, D, T>(
+    deserializer: D,
+) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value: Option<serde_json::Value> = Deserialize::deserialize(deserializer)?;
+
+    match value {
+        Some(v @ serde_json::Value::Object(_)) => serde_json::from_value(v)
+            .map(Some)
+            .map_err(serde::de::Error::custom),
+        _ => Ok(None),
+    }
+}
+
+/// Reads the content of a message, which is null when the model only calls tools.
+fn deserialize_message_content<'de, D>(
+    deserializer: D,
+) -> std::result::Result<MessageContent, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let content: Option<MessageContent> = Deserialize::deserialize(deserializer)?;
+    Ok(content.unwrap_or_default())
+}
+
+/// Reads the content of a delta, which is null when the model only calls tools.
+fn deserialize_content<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let content: Option<String> = Deserialize::deserialize(deserializer)?;
+    Ok(content.unwrap_or_default())
+}
+
+/// Reads the error sent in a stream event, either as a string or as an object with a
+/// `message` field.
+pub(crate) fn deserialize_error<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value: Option<serde_json::Value> = Deserialize::deserialize(deserializer)?;
+
+    Ok(match value {
+        None | Some(serde_json::Value::Null) => None,
+        Some(serde_json::Value::String(s)) => Some(s),
+        Some(serde_json::Value::Object(o)) => match o.get("message") {
+            Some(serde_json::Value::String(s)) => Some(s.clone()),
+            _ => Some(serde_json::Value::Object(o).to_string()),
+        },
+        Some(v) => Some(v.to_string()),
+    })
+}
+
+/// Timing statistics for a streamed chat response.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StreamStats {
+    /// Time from the creation of the collector until the first generated text was received.
+    pub time_to_first_token: Option<Duration>,
+    /// Time from the creation of the collector until the last event was received.
+    pub total_duration: Duration,
+    /// The number of events received.
+    pub chunk_count: usize,
+}
+
+/// Assembles the events of a streamed chat response into a complete [`Response`].
+///
+/// The timer for the statistics starts when the collector is created, so create it
+/// before starting the request to include the connection time.
+///
+/// # Example
+///
+/// ```ignore
+/// let collector = chat::StreamCollector::new();
+///
+/// let stream = clt.generate_chat_completion_stream(&req).await?;
+/// let (response, stats) = collector.collect(stream).await?;
+///
+/// println!("{:?} to first token", stats.time_to_first_token);
+/// ```
+#[derive(Debug)]
+pub struct StreamCollector {
+    started: Instant,
+    first_token: Option<Duration>,
+    last_event: Option<Duration>,
+    chunks: usize,
+    response: Response,
+}
+
+impl Default for StreamCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StreamCollector {
+    /// Creates a new collector and starts the timer.
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            first_token: None,
+            last_event: None,
+            chunks: 0,
+            response: Response::default(),
+        }
+    }
+
+    /// Adds an event to the response.
+    ///
+    /// ## Arguments
+    ///
+    /// * `evt` - An event received from the chat stream.
+    pub fn push(&mut self, evt: &ResponseEvents) {
+        let elapsed = self.started.elapsed();
+        self.chunks += 1;
+        self.last_event = Some(elapsed);
+
+        if self.response.id.is_empty() {
+            self.response.id = evt.id.clone();
+            self.response.object = evt.object.trim_end_matches(".chunk").to_string();
+            self.response.created = evt.created;
+            self.response.model = evt.model.clone();
+        }
+
+        for c in &evt.choices {
+            if self.first_token.is_none() && !c.delta.content.is_empty() {
+                self.first_token = Some(elapsed);
+            }
+
+            let choice = match self
+                .response
+                .choices
+                .iter_mut()
+                .position(|x| x.index == c.index)
+            {
+                Some(i) => &mut self.response.choices[i],
+                None => {
+                    self.response.choices.push(ResponseChoice {
+                        index: c.index,
+                        message: Message {
+                            role: Roles::Assistant,
+                            ..Default::default()
+                        },
+                        ..Default::default()
+                    });
+                    self.response
+                        .choices
+                        .last_mut()
+                        .expect("choice was just added")
+                }
+            };
+
+            choice.message.content.push_str(&c.delta.content);
+
+            for d in &c.delta.tool_calls {
+                let calls = &mut choice.message.tool_calls;
+                if calls.len() <= d.index {
+                    calls.resize(d.index + 1, ToolCall::default());
+                }
+
+                let call = &mut calls[d.index];
+                if let Some(id) = &d.id {
+                    call.id.clone_from(id);
+                }
+                if let Some(tool_type) = &d.tool_type {
+                    call.tool_type.clone_from(tool_type);
+                }
+                if let Some(f) = &d.function {
+                    if let Some(name) = &f.name {
+                        call.function.name.push_str(name);
+                    }
+                    if let Some(args) = &f.arguments {
+                        call.function.arguments.push_str(args);
+                    }
+                }
+            }
+
+            if let Some(logprobs) = &c.logprobs {
+                choice
+                    .logprobs
+                    .get_or_insert_with(Logprobs::default)
+                    .content
+                    .extend(logprobs.content.iter().cloned());
+            }
+
+            if c.finish_reason.is_some() {
+                choice.finish_reason.clone_from(&c.finish_reason);
+
+                // Use the full text sent with the final event if no deltas were received.
+                if let Some(text) = &c.generated_text {
+                    if choice.message.content.is_empty() {
+                        choice.message.content = MessageContent::Text(text.clone());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the assembled response and the statistics for the stream.
+    pub fn finish(mut self) -> (Response, StreamStats) {
+        self.response.choices.sort_by_key(|c| c.index);
+
+        let stats = StreamStats {
+            time_to_first_token: self.first_token,
+            total_duration: self.last_event.unwrap_or_else(|| self.started.elapsed()),
+            chunk_count: self.chunks,
+        };
+
+        (self.response, stats)
+    }
+
+    /// Consumes the stream and returns the assembled response and the statistics for
+    /// the stream. Returns the first error received from the stream.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stream` - The stream returned from the chat stream call.
+    pub async fn collect<S>(mut self, mut stream: S) -> Result<(Response, StreamStats)>
+    where
+        S: Stream<Item = Result<ResponseEvents>> + Unpin,
+    {
+        while let Some(evt) = stream.try_next().await? {
+            self.push(&evt);
+        }
+
+        Ok(self.finish())
+    }
+}
+
 /// The different role types for chat requests/respones.
 #[derive(Debug, Deserialize, Serialize, PartialEq, Default, Clone)]
 pub enum Roles {
@@ -328,6 +1246,8 @@ pub enum Roles {
     User,
     #[serde(rename = "assistant")]
     Assistant,
+    #[serde(rename = "tool")]
+    Tool,
 }
 
 #[cfg(test)]
@@ -359,13 +1279,13 @@ mod tests {
         assert_eq!(req.messages[0].role, Roles::User);
 
         let input = req.input.unwrap();
-        assert_eq!(input.block_prompt_injection, true);
+        assert!(input.block_prompt_injection);
         assert!(input.pii.is_none());
         assert!(input.pii_replace_method.is_none());
 
         let output = req.output.unwrap();
-        assert_eq!(output.factuality, true);
-        assert_eq!(output.toxicity, true);
+        assert!(output.factuality);
+        assert!(output.toxicity);
     }
 
     #[test]
@@ -399,12 +1319,363 @@ mod tests {
         assert_eq!(content[1].clone().text.expect("text prompt"), PROMPT);
 
         let input = req.input.unwrap();
-        assert_eq!(input.block_prompt_injection, true);
+        assert!(input.block_prompt_injection);
         assert_eq!(input.pii, Some(InputMethod::Block));
         assert_eq!(input.pii_replace_method, Some(ReplaceMethod::Fake));
 
         let output = req.output.unwrap();
-        assert_eq!(output.factuality, true);
-        assert_eq!(output.toxicity, true);
+        assert!(output.factuality);
+        assert!(output.toxicity);
+    }
+
+    #[test]
+    fn chat_request_mixed_content() {
+        let req = Request::<Message>::new("llava-1.5-7b-hf".to_string())
+            .add_message(Roles::System, "Describe images briefly.".to_string())
+            .add_message_parts(
+                Roles::User,
+                vec![
+                    Content::text("Compare these images.".to_string()),
+                    Content::image_url(IMAGE_URI.to_string()),
+                    Content::image_url_with_detail("Second URI".to_string(), ImageDetail::Low),
+                ],
+            );
+
+        assert_eq!(req.messages[0].content, "Describe images briefly.");
+        assert_eq!(req.messages[1].content.image_count(), 2);
+        assert_eq!(req.messages[1].content.text(), "Compare these images.");
+
+        let json = serde_json::to_value(&req).expect("json request");
+        assert_eq!(json["messages"][0]["content"], "Describe images briefly.");
+
+        let parts = &json["messages"][1]["content"];
+        assert_eq!(
+            parts[0],
+            serde_json::json!({"type": "text", "text": "Compare these images."})
+        );
+        assert_eq!(
+            parts[1],
+            serde_json::json!({"type": "image_url", "image_url": {"url": IMAGE_URI}})
+        );
+        assert_eq!(parts[2]["image_url"]["detail"], "low");
+
+        let back: Request<Message> = serde_json::from_value(json).expect("request from json");
+        assert_eq!(back.messages, req.messages);
+    }
+
+    #[test]
+    fn message_content() {
+        let mut content = MessageContent::default();
+        assert!(content.is_empty());
+        content.push_str("Hello");
+        assert_eq!(content, "Hello");
+
+        let mut content = MessageContent::Parts(vec![Content::image_url(IMAGE_URI.to_string())]);
+        assert!(!content.is_empty());
+        assert_eq!(content.as_str(), None);
+        content.push_str("What is");
+        content.push_str(" this?");
+        assert_eq!(content.parts().len(), 2);
+        assert_eq!(content.to_string(), "What is this?");
+
+        let msg: Message = MessageVision {
+            role: Roles::User,
+            content: vec![
+                Content::image_url(IMAGE_URI.to_string()),
+                Content::text(PROMPT.to_string()),
+            ],
+        }
+        .into();
+        assert_eq!(msg.content.image_count(), 1);
+        assert_eq!(msg.content.into_text(), PROMPT);
+    }
+
+    #[test]
+    fn chat_request_sampling() {
+        let req = Request::<Message>::new("Hermes-2-Pro-Llama-3-8B".to_string())
+            .add_message(Roles::User, PROMPT.to_string());
+
+        let json = serde_json::to_value(&req).expect("json request");
+        for field in [
+            "stop",
+            "presence_penalty",
+            "frequency_penalty",
+            "repetition_penalty",
+            "seed",
+            "n",
+            "logprobs",
+            "top_logprobs",
+        ] {
+            assert!(json.get(field).is_none(), "{}", field);
+        }
+
+        let req = req
+            .stop(vec!["\n\n".to_string(), "END".to_string()])
+            .presence_penalty(0.5)
+            .frequency_penalty(-0.5)
+            .repetition_penalty(1.1)
+            .seed(42)
+            .n(2)
+            .logprobs(Some(3));
+
+        let json = serde_json::to_value(&req).expect("json request");
+        assert_eq!(json["stop"], serde_json::json!(["\n\n", "END"]));
+        assert_eq!(json["presence_penalty"], 0.5);
+        assert_eq!(json["frequency_penalty"], -0.5);
+        assert_eq!(json["repetition_penalty"], 1.1);
+        assert_eq!(json["seed"], 42);
+        assert_eq!(json["n"], 2);
+        assert_eq!(json["logprobs"], true);
+        assert_eq!(json["top_logprobs"], 3);
+    }
+
+    #[test]
+    fn response_logprobs() {
+        let resp: Response = serde_json::from_str(
+            r#"{"choices":[{"index":0,"message":{"role":"assistant","content":"Hi"},"finish_reason":"stop",
+            "logprobs":{"content":[{"token":"Hi","logprob":-0.25,"bytes":[72,105],
+            "top_logprobs":[{"token":"Hi","logprob":-0.25},{"token":"Hello","logprob":-1.5}]}]}}]}"#,
+        )
+        .expect("chat response");
+
+        let logprobs = resp.choices[0].logprobs.as_ref().expect("logprobs");
+        assert_eq!(logprobs.content.len(), 1);
+        assert_eq!(logprobs.content[0].token, "Hi");
+        assert_eq!(logprobs.content[0].logprob, -0.25);
+        assert_eq!(logprobs.content[0].bytes, Some(vec![72, 105]));
+        assert_eq!(logprobs.content[0].top_logprobs[1].token, "Hello");
+
+        // Older versions of the API send a number.
+        let evt: ResponseEvents = serde_json::from_str(
+            r#"{"choices":[{"index":0,"logprobs":0,"delta":{"content":"a"}}]}"#,
+        )
+        .expect("chat event");
+        assert!(evt.choices[0].logprobs.is_none());
+    }
+
+    #[test]
+    fn chat_request_tools() {
+        let req = Request::<Message>::new("Hermes-2-Pro-Llama-3-8B".to_string())
+            .add_message(Roles::User, "What is the weather in Paris?".to_string())
+            .add_tool(Tool::function(
+                "get_weather".to_string(),
+                "Returns the weather for a city".to_string(),
+                serde_json::json!({
+                    "type": "object",
+                    "properties": { "city": { "type": "string" } },
+                    "required": ["city"],
+                }),
+            ))
+            .tool_choice(ToolChoice::Function("get_weather".to_string()))
+            .add_tool_message("call-1".to_string(), "sunny".to_string());
+
+        let json = serde_json::to_value(&req).expect("json request");
+
+        assert_eq!(json["tools"][0]["type"], "function");
+        assert_eq!(json["tools"][0]["function"]["name"], "get_weather");
+        assert_eq!(
+            json["tools"][0]["function"]["parameters"]["required"][0],
+            "city"
+        );
+        assert_eq!(json["tool_choice"]["function"]["name"], "get_weather");
+
+        assert!(json["messages"][0].get("tool_calls").is_none());
+        assert!(json["messages"][0].get("tool_call_id").is_none());
+        assert_eq!(json["messages"][1]["role"], "tool");
+        assert_eq!(json["messages"][1]["tool_call_id"], "call-1");
+        assert_eq!(json["messages"][1]["content"], "sunny");
+
+        for (choice, expected) in [
+            (ToolChoice::Auto, serde_json::json!("auto")),
+            (ToolChoice::None, serde_json::json!("none")),
+            (ToolChoice::Required, serde_json::json!("required")),
+        ] {
+            assert_eq!(serde_json::to_value(&choice).expect("json"), expected);
+            assert_eq!(
+                serde_json::from_value::<ToolChoice>(expected).expect("choice"),
+                choice
+            );
+        }
+    }
+
+    #[test]
+    fn response_tool_calls() {
+        let resp: Response = serde_json::from_str(
+            r#"{"choices":[{"index":0,"finish_reason":"tool_calls","message":{"role":"assistant","content":null,
+            "tool_calls":[{"id":"call-1","type":"function","function":{"name":"get_weather","arguments":"{\"city\":\"Paris\"}"}}]}}]}"#,
+        )
+        .expect("chat response");
+
+        let msg = &resp.choices[0].message;
+        assert_eq!(resp.choices[0].finish_reason, Some(FinishReason::ToolCalls));
+        assert!(msg.content.is_empty());
+        assert_eq!(msg.tool_calls[0].id, "call-1");
+        assert_eq!(msg.tool_calls[0].function.name, "get_weather");
+
+        let args: serde_json::Value = msg.tool_calls[0].function.parse_arguments().expect("args");
+        assert_eq!(args["city"], "Paris");
+    }
+
+    #[test]
+    fn stream_collector_tool_calls() {
+        let deltas = [
+            r#"{"choices":[{"index":0,"delta":{"role":"assistant","content":null,"tool_calls":[{"index":0,"id":"call-1","type":"function","function":{"name":"get_weather","arguments":""}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"content":null,"tool_calls":[{"index":0,"function":{"arguments":"{\"city\":"}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":1,"id":"call-2","type":"function","function":{"name":"get_time","arguments":"{}"}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"Paris\"}"}}]}}]}"#,
+            r#"{"choices":[{"index":0,"delta":{},"finish_reason":"tool_calls"}]}"#,
+        ];
+
+        let mut collector = StreamCollector::new();
+        for d in deltas {
+            collector.push(&serde_json::from_str(d).expect("chat event"));
+        }
+
+        let (resp, _) = collector.finish();
+        let calls = &resp.choices[0].message.tool_calls;
+
+        assert_eq!(resp.choices[0].finish_reason, Some(FinishReason::ToolCalls));
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call-1");
+        assert_eq!(calls[0].tool_type, "function");
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city":"Paris"}"#);
+        assert_eq!(calls[1].id, "call-2");
+        assert_eq!(calls[1].function.name, "get_time");
+    }
+
+    fn stream_event(index: i64, content: &str, finish_reason: Option<&str>) -> ResponseEvents {
+        ResponseEvents {
+            id: "chat-1".to_string(),
+            object: "chat.completion.chunk".to_string(),
+            created: 1717000000,
+            model: "Hermes-2-Pro-Llama-3-8B".to_string(),
+            choices: vec![ChoiceEvents {
+                index,
+                delta: EventsDelta {
+                    content: content.to_string(),
+                    ..Default::default()
+                },
+                finish_reason: finish_reason.map(|x| FinishReason::from(x.to_string())),
+                ..Default::default()
+            }],
+            error: None,
+        }
+    }
+
+    #[test]
+    fn stream_collector_logprobs() {
+        let mut collector = StreamCollector::new();
+
+        for (token, logprob) in [("Hello", -0.1), (" world", -0.2)] {
+            let mut evt = stream_event(0, token, None);
+            evt.choices[0].logprobs = Some(Logprobs {
+                content: vec![TokenLogprob {
+                    token: token.to_string(),
+                    logprob,
+                    ..Default::default()
+                }],
+            });
+            collector.push(&evt);
+        }
+        collector.push(&stream_event(0, "", Some("stop")));
+
+        let (resp, _) = collector.finish();
+
+        let logprobs = resp.choices[0].logprobs.as_ref().expect("logprobs");
+        let tokens: Vec<&str> = logprobs.content.iter().map(|t| t.token.as_str()).collect();
+        assert_eq!(tokens, vec!["Hello", " world"]);
+    }
+
+    #[test]
+    fn stream_collector() {
+        let mut collector = StreamCollector::new();
+
+        collector.push(&stream_event(0, "", None));
+        collector.push(&stream_event(1, "Good", None));
+        collector.push(&stream_event(0, "Hello", None));
+        collector.push(&stream_event(0, " world", None));
+        collector.push(&stream_event(1, "bye", Some("length")));
+        collector.push(&stream_event(0, "", Some("stop")));
+
+        let (resp, stats) = collector.finish();
+
+        assert_eq!(resp.id, "chat-1");
+        assert_eq!(resp.object, "chat.completion");
+        assert_eq!(resp.model, "Hermes-2-Pro-Llama-3-8B");
+        assert_eq!(resp.created, 1717000000);
+
+        assert_eq!(resp.choices.len(), 2);
+        assert_eq!(resp.choices[0].index, 0);
+        assert_eq!(resp.choices[0].message.role, Roles::Assistant);
+        assert_eq!(resp.choices[0].message.content, "Hello world");
+        assert_eq!(resp.choices[0].finish_reason, Some(FinishReason::Stop));
+        assert_eq!(resp.choices[1].message.content, "Goodbye");
+        assert_eq!(resp.choices[1].finish_reason, Some(FinishReason::Length));
+
+        assert_eq!(stats.chunk_count, 6);
+        let ttft = stats.time_to_first_token.expect("time to first token");
+        assert!(ttft <= stats.total_duration);
+    }
+
+    #[test]
+    fn stream_collector_generated_text() {
+        let mut collector = StreamCollector::new();
+
+        let mut evt = stream_event(0, "", Some("stop"));
+        evt.choices[0].generated_text = Some("Hello world".to_string());
+        collector.push(&evt);
+
+        let (resp, stats) = collector.finish();
+
+        assert_eq!(resp.choices[0].message.content, "Hello world");
+        assert_eq!(stats.chunk_count, 1);
+        assert!(stats.time_to_first_token.is_none());
+    }
+
+    #[test]
+    fn finish_reason() {
+        let cases = [
+            ("stop", FinishReason::Stop),
+            ("length", FinishReason::Length),
+            ("content_filter", FinishReason::ContentFilter),
+            ("tool_calls", FinishReason::ToolCalls),
+            ("eos_token", FinishReason::Other("eos_token".to_string())),
+        ];
+
+        for (s, reason) in cases {
+            let json = format!(r#""{}""#, s);
+            assert_eq!(serde_json::from_str::<FinishReason>(&json).unwrap(), reason);
+            assert_eq!(serde_json::to_string(&reason).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn stream_event_error() {
+        let evt: ResponseEvents = serde_json::from_str(r#"{"error":"model overloaded"}"#).unwrap();
+        assert_eq!(evt.error, Some("model overloaded".to_string()));
+
+        let evt: ResponseEvents =
+            serde_json::from_str(r#"{"error":{"message":"model overloaded","code":503}}"#).unwrap();
+        assert_eq!(evt.error, Some("model overloaded".to_string()));
+
+        let evt: ResponseEvents = serde_json::from_str(r#"{"id":"chat-1","error":null}"#).unwrap();
+        assert!(evt.error.is_none());
+    }
+
+    #[test]
+    fn response_format_strict() {
+        let schema = serde_json::json!({ "type": "object" });
+
+        let format = ResponseFormat::json_schema("weather".to_string(), schema.clone());
+        let json = serde_json::to_value(&format).expect("json format");
+        assert_eq!(json["type"], "json_schema");
+        assert!(json["json_schema"].get("strict").is_none());
+
+        let format = ResponseFormat::json_schema("weather".to_string(), schema).strict(true);
+        let json = serde_json::to_value(&format).expect("json format");
+        assert_eq!(json["json_schema"]["strict"], true);
+
+        assert_eq!(ResponseFormat::JsonObject.strict(true), ResponseFormat::JsonObject);
     }
 }
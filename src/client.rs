@@ -3,20 +3,19 @@ use std::{env, fmt, sync::Arc, time::Duration};
 
 use crate::built_info;
 use crate::{
-    chat, completion, embedding, factuality,
+    chat, completion, embedding, factuality, image,
     injection, pii, rerank, toxicity, translate,
-    tokenize, models, Result
+    tokenize, models, registry, retry::{self, RetryPolicy}, sse,
+    structured::{self, JsonSchema}, PgError, Result
 };
 use dotenvy;
-use eventsource_client::Client as EventClient;
-use eventsource_client::SSE;
-use futures::TryStreamExt;
-use log::error;
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use log::{error, warn};
 use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    ClientBuilder, Response, StatusCode,
+    header::{self, HeaderMap, HeaderName, HeaderValue},
+    RequestBuilder, Response, StatusCode,
 };
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use tokio::sync::mpsc::Sender;
 
 const USER_AGENT: &str = "Prediction Guard Rust Client";
@@ -60,8 +59,321 @@ impl PgEnvironment {
     pub fn from_env() -> Result<Self> {
         let _ = dotenvy::dotenv(); // Ignoring error - it's ok to not have .env files
         Ok(Self {
-            key: env::var("PREDICTIONGUARD_API_KEY")?,
-            host: env::var("PREDICTIONGUARD_URL")?,
+            key: env_var("PREDICTIONGUARD_API_KEY")?,
+            host: env_var("PREDICTIONGUARD_URL")?,
+        })
+    }
+}
+
+/// Builds a [`Client`] with custom connection settings.
+///
+/// The builder exposes the timeouts, proxies, TLS settings and headers used by the
+/// underlying HTTP client. A pre-built `reqwest::Client` can be provided instead, in
+/// which case the timeout, proxy and TLS settings of the builder are not used.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::time::Duration;
+/// use prediction_guard::client;
+///
+/// let clt = client::ClientBuilder::from_env()
+///     .timeout(Duration::from_secs(120))
+///     .user_agent_suffix("my-service/1.0".to_string())
+///     .build()?;
+/// ```
+#[derive(Debug)]
+pub struct ClientBuilder {
+    api_key: Option<String>,
+    host: Option<String>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+    timeout: Duration,
+    stream_timeout: Duration,
+    proxies: Vec<reqwest::Proxy>,
+    root_certificates: Vec<reqwest::Certificate>,
+    accept_invalid_certs: bool,
+    headers: HeaderMap,
+    user_agent_suffix: Option<String>,
+    http_client: Option<reqwest::Client>,
+    retry_policy: RetryPolicy,
+    validate_requests: bool,
+    models_cache_ttl: Duration,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            api_key: None,
+            host: None,
+            connect_timeout: Duration::new(30, 0),
+            read_timeout: Duration::new(30, 0),
+            timeout: Duration::new(45, 0),
+            stream_timeout: Duration::new(600, 0),
+            proxies: Vec::new(),
+            root_certificates: Vec::new(),
+            accept_invalid_certs: false,
+            headers: HeaderMap::new(),
+            user_agent_suffix: None,
+            http_client: None,
+            retry_policy: RetryPolicy::none(),
+            validate_requests: false,
+            models_cache_ttl: registry::DEFAULT_TTL,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Creates a new builder without an API key or host.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new builder with the API key and host loaded from either a `.env`
+    /// file or the `PREDICTIONGUARD_API_KEY` and `PREDICTIONGUARD_URL` environment
+    /// variables. Missing variables are reported when the client is built.
+    pub fn from_env() -> Self {
+        let _ = dotenvy::dotenv(); // Ignoring error - it's ok to not have .env files
+        Self {
+            api_key: env::var("PREDICTIONGUARD_API_KEY").ok(),
+            host: env::var("PREDICTIONGUARD_URL").ok(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the API key and host from a Prediction Guard environment.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `pg_env` - the prediction guard environment to connect to.
+    pub fn environment(self, pg_env: PgEnvironment) -> Self {
+        self.api_key(pg_env.key).host(pg_env.host)
+    }
+
+    /// Sets the API key.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `key` - the Prediction Guard API key.
+    pub fn api_key(mut self, key: String) -> Self {
+        self.api_key = Some(key);
+        self
+    }
+
+    /// Sets the base URL of the Prediction Guard API. Trailing slashes are removed and a
+    /// path prefix, e.g. `https://gateway.example.com/pg`, is kept.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `host` - the Prediction Guard URL.
+    pub fn host(mut self, host: String) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    /// Sets the timeout for connecting to the API. Defaults to 30 seconds.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `timeout` - The connect timeout.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for each read of the response. Defaults to 30 seconds.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `timeout` - The read timeout.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for a whole request, from connecting until the response body
+    /// is read. Defaults to 45 seconds.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `timeout` - The request timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout for a whole streaming call, which usually takes longer than the
+    /// other calls. The read timeout still applies between two events. Defaults to
+    /// 10 minutes.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `timeout` - The streaming request timeout.
+    pub fn stream_timeout(mut self, timeout: Duration) -> Self {
+        self.stream_timeout = timeout;
+        self
+    }
+
+    /// Adds a proxy used for the requests to the API.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `proxy` - The proxy to add.
+    pub fn proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Adds a custom root certificate, e.g. the CA of a corporate gateway.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `cert` - The certificate to trust.
+    pub fn add_root_certificate(mut self, cert: reqwest::Certificate) -> Self {
+        self.root_certificates.push(cert);
+        self
+    }
+
+    /// Disables the validation of the server certificates. This should only be used for
+    /// local development.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `accept` - Determines whether invalid certificates are accepted.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Adds a header that is sent with every request.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `name` - The name of the header.
+    /// * `value` - The value of the header.
+    pub fn default_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets a suffix that is appended to the user agent, e.g. the name and version of
+    /// the calling service.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `suffix` - The text to append to the user agent.
+    pub fn user_agent_suffix(mut self, suffix: String) -> Self {
+        self.user_agent_suffix = Some(suffix);
+        self
+    }
+
+    /// Uses a pre-built HTTP client. The timeout, proxy and TLS settings of the builder
+    /// are ignored, the headers are still sent with every request.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `http_client` - The HTTP client used to send the requests.
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Sets the retry policy used by the client.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `policy` - The retry policy applied to every call.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Enables the validation of requests against the models list before they are sent.
+    /// A request is rejected with [`PgError::Validation`] when the model does not support
+    /// it, e.g. a request with images to a text only model, or when the max tokens plus
+    /// the estimated input tokens exceed the context length of the model. Requests for
+    /// models that are not in the list are sent without validation.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `validate` - Determines whether requests are validated.
+    pub fn validate_requests(mut self, validate: bool) -> Self {
+        self.validate_requests = validate;
+        self
+    }
+
+    /// Sets how long the models list used for validation is cached. Defaults to 5 minutes.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `ttl` - The time the models list is cached.
+    pub fn models_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.models_cache_ttl = ttl;
+        self
+    }
+
+    /// Builds the client. Returns an error if the API key or host are missing or invalid.
+    pub fn build(self) -> Result<Client> {
+        let key = match self.api_key {
+            Some(k) if !k.trim().is_empty() => k,
+            _ => {
+                return Err(PgError::Config(
+                    "missing api key, set PREDICTIONGUARD_API_KEY".to_string(),
+                ))
+            }
+        };
+
+        let server = match self.host {
+            Some(h) => normalize_host(&h)?,
+            None => {
+                return Err(PgError::Config(
+                    "missing host, set PREDICTIONGUARD_URL".to_string(),
+                ))
+            }
+        };
+
+        let mut user_agent = format!("{} v{}", USER_AGENT, built_info::PKG_VERSION);
+        if let Some(suffix) = self.user_agent_suffix {
+            user_agent = format!("{} {}", user_agent, suffix);
+        }
+
+        let http = match self.http_client {
+            Some(x) => x,
+            None => {
+                let mut builder = reqwest::Client::builder()
+                    .connect_timeout(self.connect_timeout)
+                    .read_timeout(self.read_timeout)
+                    .timeout(self.timeout)
+                    .danger_accept_invalid_certs(self.accept_invalid_certs);
+
+                for proxy in self.proxies {
+                    builder = builder.proxy(proxy);
+                }
+
+                for cert in self.root_certificates {
+                    builder = builder.add_root_certificate(cert);
+                }
+
+                builder.build()?
+            }
+        };
+
+        let mut header_map = self.headers;
+        header_map.insert(header::USER_AGENT, HeaderValue::from_str(&user_agent)?);
+        header_map.insert("x-api-key", HeaderValue::from_str(&key)?);
+
+        let inner = Arc::new(ClientInner {
+            server,
+            http_client: http,
+            headers: header_map,
+            stream_timeout: self.stream_timeout,
+            registry: registry::Registry::new(self.models_cache_ttl),
+            validate_requests: self.validate_requests,
+        });
+
+        Ok(Client {
+            inner,
+            retry_policy: self.retry_policy,
         })
     }
 }
@@ -71,6 +383,7 @@ impl PgEnvironment {
 #[derive(Debug, Clone)]
 pub struct Client {
     inner: Arc<ClientInner>,
+    retry_policy: RetryPolicy,
 }
 
 #[derive(Debug)]
@@ -78,14 +391,18 @@ struct ClientInner {
     server: String,
     http_client: reqwest::Client,
     headers: HeaderMap,
-    api_key: String,
+    stream_timeout: Duration,
+    registry: registry::Registry,
+    validate_requests: bool,
 }
 
 impl Client {
     /// Creates a new instance of client to be used. Assumes the Prediction Guard keys,
     /// PREDICTIONGUARD_API_KEY and PREDICTIONGUARD_URL are set in the environment.
+    ///
+    /// Returns an error if the environment variables are not found.
     pub fn new() -> Result<Self> {
-        let pg_env = PgEnvironment::from_env().expect("env keys");
+        let pg_env = PgEnvironment::from_env()?;
 
         Self::from_environment(pg_env)
     }
@@ -96,35 +413,31 @@ impl Client {
     ///
     ///  * `pg_env` - the prediction guard environment to connect to.
     pub fn from_environment(pg_env: PgEnvironment) -> Result<Self> {
-        let user_agent = format!("{} v{}", USER_AGENT, built_info::PKG_VERSION);
-
-        let http = ClientBuilder::new()
-            .connect_timeout(Duration::new(30, 0))
-            .read_timeout(Duration::new(30, 0))
-            .timeout(Duration::new(45, 0))
-            .user_agent(user_agent)
-            .build()?;
-
-        let header_key = match HeaderValue::from_str(&pg_env.key) {
-            Ok(x) => x,
-            Err(e) => {
-                return Err(Box::new(e));
-            }
-        };
+        ClientBuilder::new().environment(pg_env).build()
+    }
 
-        let mut header_map = HeaderMap::new();
-        let _ = header_map
-            .insert("x-api-key", header_key)
-            .ok_or("invalid api key");
+    /// Returns a builder to configure the client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
 
-        let inner = Arc::new(ClientInner {
-            server: pg_env.host.to_string(),
-            http_client: http,
-            headers: header_map,
-            api_key: pg_env.key,
-        });
+    /// Returns a copy of the client that uses the given retry policy. The copy shares
+    /// the connection pool with the original client, so it is cheap to create one to
+    /// override the policy for a single call.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `policy` - The retry policy applied to every call made with the returned client.
+    pub fn with_retry_policy(&self, policy: RetryPolicy) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            retry_policy: policy,
+        }
+    }
 
-        Ok(Self { inner })
+    /// Returns the retry policy used by the client.
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
     }
 
     /// Calls the health endpoint.
@@ -132,17 +445,13 @@ impl Client {
     /// Returns the text response from the server. A 200 (Ok) status code is expected from
     /// Prediction Guard api. Any other status code is considered an error.
     pub async fn check_health(&self) -> Result<String> {
-        let result = self
+        let builder = self
             .inner
             .http_client
             .get(&self.inner.server)
-            .headers(self.inner.headers.clone())
-            .send()
-            .await?;
+            .headers(self.inner.headers.clone());
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
         let txt = result.text().await?;
 
@@ -153,31 +462,27 @@ impl Client {
     ///
     /// ## Arguments:
     ///
-    /// * `capability` - The capability of models to sort by.
+    /// * `capability` - The capability of models to filter by, e.g. [`models::Capability::ChatCompletion`].
     ///
     /// Returns a vector of strings with the model names. A 200 (Ok) status code is expected from the Prediction Guard api.
     /// Any other status code is considered an error.
-    pub async fn retrieve_model_list(&self, capability: String) -> Result<Vec<String>> {
+    pub async fn retrieve_model_list(&self, capability: models::Capability) -> Result<Vec<String>> {
         let url = format!(
             "{}{}/{}",
             &self.inner.server,
             models::PATH,
-            capability
+            capability.as_str()
         );
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .get(url)
-            .headers(self.inner.headers.clone())
-            .send()
-            .await?;
+            .headers(self.inner.headers.clone());
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let response_body: models::Response = result.json().await?;
+        let response_body: models::Response = parse_json(result).await?;
 
         let retrieve_models_response: Vec<String> = response_body
             .data
@@ -197,22 +502,24 @@ impl Client {
     /// Returns a [`embedding::Response`]. A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
     /// is considered an error.
     pub async fn embedding(&self, req: &embedding::Request) -> Result<embedding::Response> {
+        self.validate(&req.model, || {
+            let images = req.input.iter().any(|i| i.image.is_some());
+            Ok(registry::Check::embedding(images))
+        })
+        .await?;
+
         let url = format!("{}{}", &self.inner.server, embedding::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let embed_response = result.json::<embedding::Response>().await?;
+        let embed_response = parse_json::<embedding::Response>(result).await?;
 
         Ok(embed_response)
     }
@@ -229,26 +536,172 @@ impl Client {
         &self,
         req: &completion::Request,
     ) -> Result<completion::Response> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::completion(&req.prompt, req.max_tokens))
+        })
+        .await?;
+
         let url = format!("{}{}", &self.inner.server, completion::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let comp_response = result.json::<completion::Response>().await?;
+        let comp_response = parse_json::<completion::Response>(result).await?;
 
         Ok(comp_response)
     }
 
+    /// Calls the generate completion endpoint and streams the response.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`completion::Request`]
+    ///
+    /// Returns a stream of [`completion::ResponseEvents`], one for every server side event
+    /// received. The stream ends when the server sends `[DONE]`, when the server closes
+    /// the connection or at the first error. Events after a finish reason, including
+    /// events without choices, are still returned. Dropping the stream cancels the
+    /// request.
+    ///
+    /// An error sent by the server in the middle of the stream is returned as a
+    /// [`PgError::Stream`] and ends the stream. The output checks for factuality and
+    /// toxicity are not supported when streaming and are not sent with the request.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use futures::TryStreamExt;
+    ///
+    /// let mut stream = clt.generate_completion_stream(&req).await?;
+    ///
+    /// while let Some(evt) = stream.try_next().await? {
+    ///     if let Some(choice) = evt.choices.first() {
+    ///         print!("{}", choice.text);
+    ///     }
+    /// }
+    /// ```
+    pub async fn generate_completion_stream(
+        &self,
+        req: &completion::Request,
+    ) -> Result<BoxStream<'static, Result<completion::ResponseEvents>>> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::completion(&req.prompt, req.max_tokens))
+        })
+        .await?;
+
+        let url = format!("{}{}", &self.inner.server, completion::PATH);
+
+        let builder = self
+            .inner
+            .http_client
+            .post(url)
+            .headers(self.inner.headers.clone())
+            .timeout(self.inner.stream_timeout)
+            .json(&stream_body(req)?);
+
+        let result = self.send(builder).await?;
+
+        Ok(stream_events(sse::events(result).boxed()))
+    }
+
+    /// Calls the generate completion endpoint.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`completion::Request`]
+    /// * `event_handler` - Event handler function that is called when a server side event is raised.
+    ///
+    /// The generated text is returned via events from the server. The event handler function gets called
+    /// every time the client receives an event response with data. Once the server terminates the events the call returns.
+    /// The final event, which carries the finish reason (`stop`, `length`, ...), is then returned to the caller.
+    /// An error sent by the server in the middle of the stream is returned as a [`PgError::Stream`].
+    ///
+    /// Only the first choice (`index` 0) is handled, use the stream call for requests with `n` greater than 1.
+    ///
+    /// A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
+    /// is considered an error.
+    pub async fn generate_completion_events<F>(
+        &self,
+        req: &completion::Request,
+        event_handler: &mut F,
+    ) -> Result<Option<completion::ResponseEvents>>
+    where
+        F: FnMut(&String),
+    {
+        let mut stream = self.generate_completion_stream(req).await?;
+
+        while let Some(resp) = stream.try_next().await? {
+            let Some(choice) = resp.choices.iter().find(|c| c.index == 0) else {
+                continue;
+            };
+
+            if choice.finish_reason.is_some() {
+                if !choice.text.is_empty() {
+                    event_handler(&choice.text);
+                }
+                return Ok(Some(resp));
+            }
+
+            event_handler(&choice.text);
+        }
+
+        Ok(None)
+    }
+
+    /// Calls the generate completion endpoint.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`completion::Request`]
+    /// * `sender` - A sender instance for a channel where there is a receiver waiting for a message.
+    ///
+    /// The generated text is returned via events from the server. The sender gets called
+    /// every time the client receives an event response with data. Once the server terminates the events the call returns.
+    /// The receiver should handle the `stop` message which means there are no more messages to receive and exit.
+    /// The final event, which carries the finish reason (`stop`, `length`, ...), is then returned to the caller.
+    /// An error sent by the server in the middle of the stream is returned as a [`PgError::Stream`].
+    ///
+    /// Only the first choice (`index` 0) is handled, use the stream call for requests with `n` greater than 1.
+    ///
+    /// A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
+    /// is considered an error.
+    pub async fn generate_completion_events_async(
+        &self,
+        req: &completion::Request,
+        sender: &Sender<String>,
+    ) -> Result<Option<completion::ResponseEvents>> {
+        let mut stream = self.generate_completion_stream(req).await?;
+
+        while let Some(resp) = stream.try_next().await? {
+            let Some(choice) = resp.choices.iter().find(|c| c.index == 0) else {
+                continue;
+            };
+
+            if choice.finish_reason.is_some() {
+                if !choice.text.is_empty() {
+                    let _ = sender.send(choice.text.clone()).await;
+                }
+                let _ = sender.send("stop".to_string()).await;
+                return Ok(Some(resp));
+            }
+
+            let msg = choice.text.clone();
+
+            if let Err(e) = sender.send(msg).await {
+                error!("generate_completion_events_async - error sending on channel, {e}");
+            }
+        }
+
+        let _ = sender.send("stop".to_string()).await;
+        Ok(None)
+    }
+
     /// Calls the generate chat completion endpoint.
     ///
     /// ## Arguments:
@@ -261,112 +714,215 @@ impl Client {
         &self,
         req: &chat::Request<chat::Message>,
     ) -> Result<chat::Response> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::chat(&serde_json::to_value(&req.messages)?, req.max_tokens))
+        })
+        .await?;
+
         let url = format!("{}{}", &self.inner.server, chat::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let chat_response = result.json::<chat::Response>().await?;
+        let chat_response = parse_json::<chat::Response>(result).await?;
 
         Ok(chat_response)
     }
 
-    /// Calls the generate chat completion endpoint.
+    /// Calls the generate chat completion endpoint and parses the response into a value
+    /// of type `T`, using the default [`structured::Options`].
     ///
     /// ## Arguments:
     ///
     /// * `req` - An instance of [`chat::Request::<Message>`]
+    ///
+    /// See [`Client::generate_structured_with`].
+    pub async fn generate_structured<T>(&self, req: &chat::Request<chat::Message>) -> Result<T>
+    where
+        T: DeserializeOwned + JsonSchema,
+    {
+        self.generate_structured_with(req, &structured::Options::default())
+            .await
+    }
+
+    /// Calls the generate chat completion endpoint and parses the response into a value
+    /// of type `T`.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`chat::Request::<Message>`]
+    /// * `options` - The number of retries and whether to set the response format.
+    ///
+    /// The JSON schema of `T` is added to the instructions of the request and, unless
+    /// disabled in the options or already set on the request, sent as the response format.
+    /// If the response cannot be parsed into `T`, the model is asked again with the parse
+    /// error. Once the retries are exhausted a [`PgError::Structured`] is returned with
+    /// the last error and response.
+    ///
+    /// A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
+    /// is considered an error.
+    pub async fn generate_structured_with<T>(
+        &self,
+        req: &chat::Request<chat::Message>,
+        options: &structured::Options,
+    ) -> Result<T>
+    where
+        T: DeserializeOwned + JsonSchema,
+    {
+        let (name, schema) = structured::schema::<T>();
+
+        let mut req = req.clone();
+        structured::add_instructions(&mut req.messages, &schema);
+
+        if options.uses_response_format() && req.response_format.is_none() {
+            req.response_format = Some(chat::ResponseFormat::json_schema(name, schema));
+        }
+
+        let attempts = options.attempts();
+        let mut attempt = 1;
+
+        loop {
+            let resp = self.generate_chat_completion(&req).await?;
+
+            let content = resp
+                .choices
+                .into_iter()
+                .next()
+                .map(|c| c.message.content.into_text())
+                .unwrap_or_default();
+
+            let message = match structured::parse::<T>(&content) {
+                Ok(x) => return Ok(x),
+                Err(e) => e,
+            };
+
+            if attempt >= attempts {
+                return Err(PgError::Structured {
+                    attempts,
+                    message,
+                    content,
+                });
+            }
+
+            warn!("invalid structured output on attempt {attempt}, {message}");
+
+            req.messages
+                .push(chat::Message::new(chat::Roles::Assistant, content));
+            req.messages.push(chat::Message::new(
+                chat::Roles::User,
+                structured::retry_prompt(&message),
+            ));
+
+            attempt += 1;
+        }
+    }
+
+    /// Calls the generate chat completion endpoint and streams the response.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`chat::Request::<Message>`] or, for chat vision,
+    ///   [`chat::Request::<MessageVision>`]
+    ///
+    /// Returns a stream of [`chat::ResponseEvents`], one for every server side event
+    /// received. The stream ends when the server sends `[DONE]`, when the server closes
+    /// the connection or at the first error. Events after a finish reason, including
+    /// events without choices, are still returned. Dropping the stream cancels the
+    /// request.
+    ///
+    /// An error sent by the server in the middle of the stream, either as an `error`
+    /// event or as an event with an `error` field, is returned as a [`PgError::Stream`]
+    /// and ends the stream.
+    ///
+    /// The output checks for factuality and toxicity are not supported when streaming
+    /// and are not sent with the request, see [`crate::moderation::Moderator`] to check
+    /// the streamed text instead.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use futures::TryStreamExt;
+    ///
+    /// let mut stream = clt.generate_chat_completion_stream(&req).await?;
+    ///
+    /// while let Some(evt) = stream.try_next().await? {
+    ///     if let Some(choice) = evt.choices.first() {
+    ///         print!("{}", choice.delta.content);
+    ///     }
+    /// }
+    /// ```
+    pub async fn generate_chat_completion_stream<T: Serialize>(
+        &self,
+        req: &chat::Request<T>,
+    ) -> Result<BoxStream<'static, Result<chat::ResponseEvents>>> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::chat(&serde_json::to_value(&req.messages)?, req.max_tokens))
+        })
+        .await?;
+
+        let url = format!("{}{}", &self.inner.server, chat::PATH);
+
+        let builder = self
+            .inner
+            .http_client
+            .post(url)
+            .headers(self.inner.headers.clone())
+            .timeout(self.inner.stream_timeout)
+            .json(&stream_body(req)?);
+
+        let result = self.send(builder).await?;
+
+        Ok(stream_events(sse::events(result).boxed()))
+    }
+
+    /// Calls the generate chat completion endpoint.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `req` - An instance of [`chat::Request::<Message>`] or [`chat::Request::<MessageVision>`]
     /// * `event_handler` - Event handler function that is called when a server side event is raised.
     ///
     /// Returns an instance of [`chat::Response`].
     ///
     /// The generated text is returned via events from the server. The event handler function gets called
     /// every time the client receives an event response with data. Once the server terminates the events the call returns.
-    /// The entire [`chat::Response`] response is then returned to the caller.
+    /// The final event, which carries the finish reason (`stop`, `length`, ...), is then returned to the caller.
+    /// An error sent by the server in the middle of the stream is returned as a [`PgError::Stream`].
+    ///
+    /// Only the first choice (`index` 0) is handled, use the stream call for requests with `n` greater than 1.
     ///
     /// A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
     /// is considered an error.
-    pub async fn generate_chat_completion_events<F>(
+    pub async fn generate_chat_completion_events<T, F>(
         &self,
-        req: &mut chat::Request<chat::Message>,
+        req: &mut chat::Request<T>,
         event_handler: &mut F,
     ) -> Result<Option<chat::ResponseEvents>>
     where
+        T: Serialize,
         F: FnMut(&String),
     {
-        let url = format!("{}{}", &self.inner.server, chat::PATH);
-
-        req.stream = true;
-        req.output = None;
-
-        let body = serde_json::to_string(&req)?;
-
-        let user_agent = format!("{} v{}", USER_AGENT, built_info::PKG_VERSION);
+        let mut stream = self.generate_chat_completion_stream(req).await?;
 
-        let key = format!("Bearer {}", &self.inner.api_key);
+        while let Some(resp) = stream.try_next().await? {
+            let Some(choice) = resp.choices.iter().find(|c| c.index == 0) else {
+                continue;
+            };
 
-        let client = eventsource_client::ClientBuilder::for_url(&url)?
-            .header("User-Agent", user_agent.as_str())?
-            .header("Authorization", &key)?
-            .method("POST".to_string())
-            .body(body)
-            .build();
-
-        let mut stream = Box::pin(client.stream());
-
-        loop {
-            match stream.try_next().await {
-                Ok(Some(event)) => {
-                    match event {
-                        SSE::Comment(_) => continue,
-                        SSE::Event(evt) => {
-                            // Check for [DONE]
-                            if evt.data == "[DONE]" {
-                                return Ok(None);
-                            }
-
-                            // JSON Response
-                            let resp: chat::ResponseEvents = match serde_json::from_str(&evt.data) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    return Err(Box::from(ApiError {
-                                        error: format!("error parsing stream response: {}", e),
-                                    }));
-                                }
-                            };
-
-                            if resp.choices.is_empty() {
-                                // No data to stream or Done
-                                continue;
-                            }
-
-                            // Finish Reason == Stop That is the final Response.
-                            if resp.choices[0].finish_reason == Some("stop".to_string()) {
-                                return Ok(Some(resp));
-                            }
-
-                            let msg = resp.choices[0].delta.clone().content;
-                            event_handler(&msg);
-                        }
-                    }
+            if choice.finish_reason.is_some() {
+                if !choice.delta.content.is_empty() {
+                    event_handler(&choice.delta.content);
                 }
-
-                Ok(None) => continue,
-                Err(e) => match e {
-                    eventsource_client::Error::StreamClosed => break,
-                    _ => return Err(stream_error_into_api_err(e).await),
-                },
+                return Ok(Some(resp));
             }
+
+            event_handler(&choice.delta.content);
         }
 
         Ok(None)
@@ -376,7 +932,7 @@ impl Client {
     ///
     /// ## Arguments:
     ///
-    /// * `req` - An instance of [`chat::Request::<Message>`]
+    /// * `req` - An instance of [`chat::Request::<Message>`] or [`chat::Request::<MessageVision>`]
     /// * `sender` - A sender instance for a channel where there is a receiver waiting for a message.
     ///
     /// Returns an instance of [`chat::Response`].
@@ -384,88 +940,41 @@ impl Client {
     /// The generated text is returned via events from the server. The sender gets called
     /// every time the client receives an event response with data. Once the server terminates the events the call returns.
     /// The receiver should handle the `stop` message which means there are no more messages to receive and exit.
-    /// The entire [`chat::Response`] response is then returned to the caller.
+    /// The final event, which carries the finish reason (`stop`, `length`, ...), is then returned to the caller.
+    /// An error sent by the server in the middle of the stream is returned as a [`PgError::Stream`].
+    ///
+    /// Only the first choice (`index` 0) is handled, use the stream call for requests with `n` greater than 1.
     ///
     /// A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
     /// is considered an error.
-    pub async fn generate_chat_completion_events_async(
+    pub async fn generate_chat_completion_events_async<T: Serialize>(
         &self,
-        req: &mut chat::Request<chat::Message>,
+        req: &mut chat::Request<T>,
         sender: &Sender<String>,
     ) -> Result<Option<chat::ResponseEvents>> {
-        let url = format!("{}{}", &self.inner.server, chat::PATH);
-
-        req.stream = true;
-        req.output = None;
-
-        let body = serde_json::to_string(&req)?;
-
-        let user_agent = format!("{} v{}", USER_AGENT, built_info::PKG_VERSION);
-
-        let key = format!("Bearer {}", &self.inner.api_key);
-
-        let client = eventsource_client::ClientBuilder::for_url(&url)?
-            .header("User-Agent", user_agent.as_str())?
-            .header("Authorization", &key)?
-            .method("POST".to_string())
-            .body(body)
-            .build();
+        let mut stream = self.generate_chat_completion_stream(req).await?;
 
-        let mut stream = Box::pin(client.stream());
+        while let Some(resp) = stream.try_next().await? {
+            let Some(choice) = resp.choices.iter().find(|c| c.index == 0) else {
+                continue;
+            };
 
-        loop {
-            match stream.try_next().await {
-                Ok(Some(event)) => {
-                    match event {
-                        SSE::Comment(_) => continue,
-                        SSE::Event(evt) => {
-                            // Check for [DONE]
-                            if evt.data.to_lowercase() == "[done]" {
-                                let _ = sender.send("stop".to_string()).await;
-                                return Ok(None);
-                            }
-
-                            // JSON Response
-                            let resp: chat::ResponseEvents = match serde_json::from_str(&evt.data) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    return Err(Box::from(ApiError {
-                                        error: format!("error parsing stream response: {}", e),
-                                    }));
-                                }
-                            };
-
-                            if resp.choices.is_empty() {
-                                // No data to stream or Done
-                                continue;
-                            }
-
-                            // Finish Reason == Stop That is the final Response.
-                            if resp.choices[0].finish_reason == Some("stop".to_string()) {
-                                let _ = sender.send("stop".to_string()).await;
-                                return Ok(Some(resp));
-                            }
-
-                            let msg = resp.choices[0].delta.clone().content;
-
-                            match sender.send(msg).await {
-                                Ok(_) => (),
-                                Err(e) => {
-                                    error!("generate_chat_completion_events_async - error sending on channel, {e}");
-                                }
-                            }
-                        }
-                    }
+            if choice.finish_reason.is_some() {
+                if !choice.delta.content.is_empty() {
+                    let _ = sender.send(choice.delta.content.clone()).await;
                 }
+                let _ = sender.send("stop".to_string()).await;
+                return Ok(Some(resp));
+            }
+
+            let msg = choice.delta.content.clone();
 
-                Ok(None) => continue,
-                Err(e) => match e {
-                    eventsource_client::Error::StreamClosed => break,
-                    _ => return Err(stream_error_into_api_err(e).await),
-                },
+            if let Err(e) = sender.send(msg).await {
+                error!("generate_chat_completion_events_async - error sending on channel, {e}");
             }
         }
 
+        let _ = sender.send("stop".to_string()).await;
         Ok(None)
     }
 
@@ -477,30 +986,60 @@ impl Client {
     ///
     /// Returns an instance of [`chat::Response`]. A 200 (Ok) status code is expected from the Prediction Guard api. Any other status code
     /// is considered an error.
+    ///
+    /// To receive the response progressively pass the request to
+    /// [`Client::generate_chat_completion_stream`]. Messages with any number of text and
+    /// image parts, mixed with text only messages, can also be sent with
+    /// [`Client::generate_chat_completion`] using [`chat::Request::add_message_parts`].
     pub async fn generate_chat_vision(
         &self,
         req: &chat::Request<chat::MessageVision>,
     ) -> Result<chat::Response> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::chat(&serde_json::to_value(&req.messages)?, req.max_tokens))
+        })
+        .await?;
+
         let url = format!("{}{}", &self.inner.server, chat::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let chat_response = result.json::<chat::Response>().await?;
+        let chat_response = parse_json::<chat::Response>(result).await?;
 
         Ok(chat_response)
     }
 
+    /// Downloads an image with the HTTP settings of the client, e.g. the timeouts,
+    /// proxies, certificates and retry policy. Only the user agent header is sent, the
+    /// API key and custom headers are not sent to the image host.
+    ///
+    /// ## Arguments:
+    ///
+    /// * `url` - The url of the image to download.
+    /// * `max_size` - The maximum size of the image in bytes, see [`image::DEFAULT_MAX_SIZE`].
+    ///
+    /// Returns an [`image::Image`] that can be encoded as a data URI. An error is returned if
+    /// the image is too large or is not a PNG, JPEG, GIF or WebP image.
+    pub async fn download_image(&self, url: String, max_size: usize) -> Result<image::Image> {
+        let mut headers = HeaderMap::new();
+        if let Some(agent) = self.inner.headers.get(header::USER_AGENT) {
+            headers.insert(header::USER_AGENT, agent.clone());
+        }
+
+        let builder = self.inner.http_client.get(url).headers(headers);
+
+        let result = self.send(builder).await?;
+
+        image::Image::from_response(result, max_size).await
+    }
+
     /// Calls the rerank endpoint.
     ///
     /// ## Arguments:
@@ -515,20 +1054,16 @@ impl Client {
     ) -> Result<rerank::Response> {
         let url = format!("{}{}", &self.inner.server, rerank::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let rerank_response = result.json::<rerank::Response>().await?;
+        let rerank_response = parse_json::<rerank::Response>(result).await?;
 
         Ok(rerank_response)
     }
@@ -547,20 +1082,16 @@ impl Client {
     ) -> Result<factuality::Response> {
         let url = format!("{}{}", &self.inner.server, factuality::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let fact_response = result.json::<factuality::Response>().await?;
+        let fact_response = parse_json::<factuality::Response>(result).await?;
 
         Ok(fact_response)
     }
@@ -576,20 +1107,16 @@ impl Client {
     pub async fn translate(&self, req: &translate::Request) -> Result<translate::Response> {
         let url = format!("{}{}", &self.inner.server, translate::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let translate_response = result.json::<translate::Response>().await?;
+        let translate_response = parse_json::<translate::Response>(result).await?;
 
         Ok(translate_response)
     }
@@ -605,20 +1132,16 @@ impl Client {
     pub async fn pii(&self, req: &pii::Request) -> Result<pii::Response> {
         let url = format!("{}{}", &self.inner.server, pii::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let pii_response = result.json::<pii::Response>().await?;
+        let pii_response = parse_json::<pii::Response>(result).await?;
 
         Ok(pii_response)
     }
@@ -634,20 +1157,16 @@ impl Client {
     pub async fn injection(&self, req: &injection::Request) -> Result<injection::Response> {
         let url = format!("{}{}", &self.inner.server, injection::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let injection_response = result.json::<injection::Response>().await?;
+        let injection_response = parse_json::<injection::Response>(result).await?;
 
         Ok(injection_response)
     }
@@ -663,20 +1182,16 @@ impl Client {
     pub async fn toxicity(&self, req: &toxicity::Request) -> Result<toxicity::Response> {
         let url = format!("{}{}", &self.inner.server, toxicity::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let toxicity_response = result.json::<toxicity::Response>().await?;
+        let toxicity_response = parse_json::<toxicity::Response>(result).await?;
 
         Ok(toxicity_response)
     }
@@ -693,22 +1208,23 @@ impl Client {
         &self,
         req: &tokenize::Request,
     ) -> Result<tokenize::Response> {
+        self.validate(&req.model, || {
+            Ok(registry::Check::new(models::Capability::Tokenize))
+        })
+        .await?;
+
         let url = format!("{}{}", &self.inner.server, tokenize::PATH);
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .post(url)
             .headers(self.inner.headers.clone())
-            .json(req)
-            .send()
-            .await?;
+            .json(req);
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let token_response = result.json::<tokenize::Response>().await?;
+        let token_response = parse_json::<tokenize::Response>(result).await?;
 
         Ok(token_response)
     }
@@ -728,40 +1244,373 @@ impl Client {
         if let Some(request) = req {
             if let Some(capability) = &request.capability {
                 url.push('/');
-                url.push_str(capability);
+                url.push_str(capability.as_str());
             }
         }
 
-        let result = self
+        let builder = self
             .inner
             .http_client
             .get(url)
-            .headers(self.inner.headers.clone())
-            .send()
-            .await?;
+            .headers(self.inner.headers.clone());
 
-        if result.status() != StatusCode::OK {
-            return Err(retrieve_error(result).await);
-        }
+        let result = self.send(builder).await?;
 
-        let model_response = result.json::<models::Response>().await?;
+        let model_response = parse_json::<models::Response>(result).await?;
 
         Ok(model_response)
     }
+
+    /// Returns the metadata of a model from the cached models list. The list is loaded
+    /// from the models endpoint when it is not cached or the cache has expired, see
+    /// [`ClientBuilder::models_cache_ttl`].
+    ///
+    /// ## Arguments:
+    ///
+    /// * `model` - The id of the model.
+    ///
+    /// Returns `None` if the model is not in the list.
+    pub async fn model_info(&self, model: &str) -> Result<Option<models::ModelData>> {
+        let models = self.cached_models().await?;
+
+        Ok(models.get(model).cloned())
+    }
+
+    /// Removes the cached models list, it is loaded again when it is next needed.
+    pub async fn clear_models_cache(&self) {
+        self.inner.registry.clear().await;
+    }
 }
 
-async fn retrieve_error(resp: Response) -> Box<dyn std::error::Error> {
-    let err = match resp.json::<ApiError>().await {
+impl Client {
+    async fn cached_models(&self) -> Result<registry::Models> {
+        self.inner.registry.models(|| self.models(None)).await
+    }
+
+    /// Validates a request against the cached models list when the validation is enabled.
+    /// The check is only built when it is needed.
+    async fn validate<F>(&self, model: &str, check: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<registry::Check>,
+    {
+        if !self.inner.validate_requests {
+            return Ok(());
+        }
+
+        let models = self.cached_models().await?;
+
+        match models.get(model) {
+            Some(data) => check()?.validate(data),
+            None => {
+                warn!("model {} is not in the models list, the request is not validated", model);
+                Ok(())
+            }
+        }
+    }
+
+    /// Sends the request, retrying it according to the retry policy. Returns the
+    /// response when a 200 (Ok) status code is received, otherwise an error.
+    async fn send(&self, builder: RequestBuilder) -> Result<Response> {
+        let policy = &self.retry_policy;
+        let mut attempt = 1;
+
+        loop {
+            let req = builder
+                .try_clone()
+                .ok_or_else(|| PgError::Config("request body can not be retried".to_string()))?;
+
+            let delay = match req.send().await {
+                Ok(resp) if resp.status() == StatusCode::OK => return Ok(resp),
+                Ok(resp) => {
+                    if attempt >= policy.attempts() || !policy.retry_status(resp.status()) {
+                        return Err(retrieve_error(resp).await);
+                    }
+                    policy.delay(attempt, retry::retry_after(resp.headers()))
+                }
+                Err(e) => {
+                    let err = PgError::from(e);
+                    if attempt >= policy.attempts() || !policy.retry_error(&err) {
+                        return Err(err);
+                    }
+                    policy.delay(attempt, None)
+                }
+            };
+
+            warn!("request failed on attempt {attempt}, retrying in {delay:?}");
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Serializes a request for a streaming call. The output checks are removed since they
+/// are not supported when streaming.
+fn stream_body<T: Serialize>(req: &T) -> Result<serde_json::Value> {
+    let mut body = serde_json::to_value(req)?;
+
+    if let Some(fields) = body.as_object_mut() {
+        fields.insert("stream".to_string(), serde_json::Value::Bool(true));
+        fields.remove("output");
+    }
+
+    Ok(body)
+}
+
+/// A response received as a server sent event from one of the streaming endpoints.
+trait StreamResponse: DeserializeOwned + Send + 'static {
+    /// Takes the error sent by the server in the event, if there is one.
+    fn take_error(&mut self) -> Option<String>;
+}
+
+impl StreamResponse for chat::ResponseEvents {
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+impl StreamResponse for completion::ResponseEvents {
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+/// Turns the server sent events of a streaming endpoint into a stream of responses.
+///
+/// The stream ends after `[DONE]`, when the server terminates the events or after the
+/// first error. Events after a finish reason are still returned, since other choices
+/// or a final usage event can follow it.
+fn stream_events<T: StreamResponse>(
+    events: BoxStream<'static, Result<sse::Event>>,
+) -> BoxStream<'static, Result<T>> {
+    stream::unfold(Some(events), |events| async move {
+        let mut events = events?;
+
+        let evt = match events.try_next().await {
+            Ok(Some(evt)) => evt,
+            Ok(None) => return None,
+            Err(e) => return Some((Err(e), None)),
+        };
+
+        // Check for [DONE]
+        if evt.data.eq_ignore_ascii_case("[done]") {
+            return None;
+        }
+
+        if evt.event == "error" {
+            return Some((Err(PgError::Stream(stream_error(&evt.data))), None));
+        }
+
+        // JSON Response
+        let mut resp: T = match serde_json::from_str(&evt.data) {
+            Ok(x) => x,
+            Err(e) => return Some((Err(e.into()), None)),
+        };
+
+        if let Some(err) = resp.take_error() {
+            return Some((Err(PgError::Stream(err)), None));
+        }
+
+        Some((Ok(resp), Some(events)))
+    })
+    .boxed()
+}
+
+/// Reads the message of an `error` event, which is either JSON with an `error` or
+/// `message` field or plain text.
+fn stream_error(data: &str) -> String {
+    let value = match serde_json::from_str::<serde_json::Value>(data) {
         Ok(x) => x,
-        Err(e) => return Box::from(format!("error parsing error response, {}", e)),
+        Err(_) => return data.to_string(),
     };
 
-    err.into()
+    let err = value.get("error").unwrap_or(&value);
+    match err.get("message").or(Some(err)) {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        _ => data.to_string(),
+    }
 }
 
-async fn stream_error_into_api_err(err: eventsource_client::Error) -> Box<dyn std::error::Error> {
-    let msg = format!("{}", err);
-    Box::from(ApiError {
-        error: msg.to_string(),
-    })
+/// Validates the host URL and removes any trailing slashes.
+fn normalize_host(host: &str) -> Result<String> {
+    let url = reqwest::Url::parse(host.trim())
+        .map_err(|e| PgError::Config(format!("invalid host {}, {}", host, e)))?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(PgError::Config(format!(
+            "invalid host {}, the scheme must be http or https",
+            host
+        )));
+    }
+
+    if url.host_str().is_none() || url.query().is_some() || url.fragment().is_some() {
+        return Err(PgError::Config(format!(
+            "invalid host {}, expected a base url such as https://api.predictionguard.com",
+            host
+        )));
+    }
+
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+fn env_var(key: &str) -> Result<String> {
+    env::var(key).map_err(|e| PgError::Config(format!("{}, {}", key, e)))
+}
+
+async fn retrieve_error(resp: Response) -> PgError {
+    let status = resp.status();
+
+    let body = match resp.text().await {
+        Ok(x) => x,
+        Err(e) => return e.into(),
+    };
+
+    let message = serde_json::from_str::<ApiError>(&body)
+        .ok()
+        .map(|x| x.error);
+
+    PgError::Http {
+        status,
+        message,
+        body,
+    }
+}
+
+async fn parse_json<T: DeserializeOwned>(resp: Response) -> Result<T> {
+    let body = resp.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_normalization() {
+        let cases = [
+            ("https://api.predictionguard.com", "https://api.predictionguard.com"),
+            ("https://api.predictionguard.com/", "https://api.predictionguard.com"),
+            ("  http://localhost:8080// ", "http://localhost:8080"),
+            ("https://gateway.example.com/pg/", "https://gateway.example.com/pg"),
+            ("https://gateway.example.com/pg/v1", "https://gateway.example.com/pg/v1"),
+        ];
+
+        for (host, expected) in cases {
+            assert_eq!(normalize_host(host).expect("valid host"), expected);
+        }
+
+        for host in [
+            "api.predictionguard.com",
+            "localhost:8080",
+            "ftp://api.predictionguard.com",
+            "https://api.predictionguard.com?x=1",
+            "",
+        ] {
+            assert!(matches!(normalize_host(host), Err(PgError::Config(_))), "{}", host);
+        }
+    }
+
+    #[test]
+    fn builder_missing_values() {
+        let err = ClientBuilder::new()
+            .host("https://api.predictionguard.com".to_string())
+            .build()
+            .expect_err("missing api key");
+        assert!(matches!(err, PgError::Config(_)));
+
+        let err = ClientBuilder::new()
+            .api_key("api-key".to_string())
+            .build()
+            .expect_err("missing host");
+        assert!(matches!(err, PgError::Config(_)));
+
+        let clt = ClientBuilder::new()
+            .api_key("api-key".to_string())
+            .host("https://api.predictionguard.com/".to_string())
+            .user_agent_suffix("tests/1.0".to_string())
+            .build()
+            .expect("client value");
+        assert_eq!(clt.inner.server, "https://api.predictionguard.com");
+        let user_agent = clt.inner.headers[header::USER_AGENT].to_str().expect("user agent");
+        assert!(user_agent.ends_with(" tests/1.0"));
+    }
+
+    fn sse_events(events: &[(&str, &str)]) -> BoxStream<'static, Result<sse::Event>> {
+        let events: Vec<Result<sse::Event>> = events
+            .iter()
+            .map(|(event, data)| {
+                Ok(sse::Event {
+                    event: event.to_string(),
+                    data: data.to_string(),
+                    id: None,
+                    retry: None,
+                })
+            })
+            .collect();
+
+        stream::iter(events).boxed()
+    }
+
+    #[test]
+    fn stream_events_finish_reasons() {
+        let events = sse_events(&[
+            ("message", r#"{"choices":[{"index":0,"delta":{"content":"a"}},{"index":1,"delta":{"content":"b"}}]}"#),
+            ("message", r#"{"choices":[{"index":0,"delta":{"content":""},"finish_reason":"length"}]}"#),
+            ("message", r#"{"choices":[{"index":1,"delta":{"content":""},"finish_reason":"stop"}]}"#),
+            ("message", r#"{"choices":[]}"#),
+            ("message", "[DONE]"),
+            ("message", r#"{"choices":[{"index":0,"delta":{"content":"ignored"}}]}"#),
+        ]);
+
+        let stream = stream_events::<chat::ResponseEvents>(events);
+        let resps: Vec<chat::ResponseEvents> =
+            tokio_test::block_on(stream.try_collect()).expect("stream events");
+
+        assert_eq!(resps.len(), 4);
+        assert_eq!(resps[1].choices[0].finish_reason, Some(chat::FinishReason::Length));
+        assert_eq!(resps[2].choices[0].finish_reason, Some(chat::FinishReason::Stop));
+        assert!(resps[3].choices.is_empty());
+    }
+
+    #[test]
+    fn stream_events_late_choice() {
+        let events = sse_events(&[
+            ("message", r#"{"choices":[{"index":0,"delta":{"content":"a"},"finish_reason":"stop"}]}"#),
+            ("message", r#"{"choices":[{"index":1,"delta":{"content":"b"}}]}"#),
+            ("message", r#"{"choices":[{"index":1,"delta":{"content":""},"finish_reason":"stop"}]}"#),
+        ]);
+
+        let stream = stream_events::<chat::ResponseEvents>(events);
+        let resps: Vec<chat::ResponseEvents> =
+            tokio_test::block_on(stream.try_collect()).expect("stream events");
+
+        assert_eq!(resps.len(), 3);
+        assert_eq!(resps[1].choices[0].delta.content, "b");
+    }
+
+    #[test]
+    fn stream_events_errors() {
+        let cases = [
+            ("error", r#"{"error":{"message":"model overloaded"}}"#),
+            ("error", "model overloaded"),
+            ("message", r#"{"error":"model overloaded"}"#),
+        ];
+
+        for (event, data) in cases {
+            let events = sse_events(&[
+                ("message", r#"{"choices":[{"index":0,"delta":{"content":"a"}}]}"#),
+                (event, data),
+                ("message", r#"{"choices":[{"index":0,"delta":{"content":"b"}}]}"#),
+            ]);
+
+            let stream = stream_events::<chat::ResponseEvents>(events);
+            let resps: Vec<Result<chat::ResponseEvents>> = tokio_test::block_on(stream.collect());
+
+            assert_eq!(resps.len(), 2, "{}", data);
+            assert!(resps[0].is_ok());
+            match &resps[1] {
+                Err(PgError::Stream(msg)) => assert_eq!(msg, "model overloaded"),
+                x => panic!("expected stream error, got {:?}", x),
+            }
+        }
+    }
 }
@@ -1,13 +1,18 @@
 //! Data types that are used for the completion endpoints.
+use std::collections::HashMap;
+
 use serde::{self, Deserialize, Serialize};
 
-use crate::pii;
+use crate::{
+    chat::{deserialize_logprobs, FinishReason},
+    pii,
+};
 
 /// Path to the completions endpoint.
 pub const PATH: &str = "/completions";
 
 /// Allows to request PII check and Injection check on the inputs in the chat request.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct RequestInput {
     pub(crate) block_prompt_injection: bool,
     pub(crate) pii: Option<pii::InputMethod>,
@@ -15,14 +20,14 @@ pub struct RequestInput {
 }
 
 /// Allows for checking the output of the request for factuality and toxicity.
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestOutput {
     pub factuality: bool,
     pub toxicity: bool,
 }
 
 /// Completion request for the base completion endpoint.
-#[derive(Debug, Deserialize, Default, Serialize)]
+#[derive(Debug, Deserialize, Default, Clone, Serialize)]
 pub struct Request {
     pub(crate) model: String,
     pub(crate) prompt: String,
@@ -30,8 +35,25 @@ pub struct Request {
     pub(crate) temperature: Option<f64>,
     pub(crate) top_p: Option<f64>,
     pub(crate) top_k: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) repetition_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) n: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) logprobs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) top_logprobs: Option<i64>,
     pub(crate) input: Option<RequestInput>,
     pub(crate) output: Option<RequestOutput>,
+    pub(crate) stream: bool,
 }
 
 impl Request {
@@ -89,6 +111,78 @@ impl Request {
         self
     }
 
+    /// Sets the stop sequences for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stop` - The sequences where the model stops generating further tokens.
+    pub fn stop(mut self, stop: Vec<String>) -> Request {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Sets the presence penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Penalizes tokens that already appeared in the text, between -2.0 and 2.0.
+    pub fn presence_penalty(mut self, penalty: f64) -> Request {
+        self.presence_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the frequency penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Penalizes tokens based on how often they appeared in the text, between -2.0 and 2.0.
+    pub fn frequency_penalty(mut self, penalty: f64) -> Request {
+        self.frequency_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the repetition penalty for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `penalty` - Multiplicative penalty for repeated tokens, 1.0 means no penalty.
+    pub fn repetition_penalty(mut self, penalty: f64) -> Request {
+        self.repetition_penalty = Some(penalty);
+        self
+    }
+
+    /// Sets the seed for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `seed` - The seed used for sampling, to make the output reproducible.
+    pub fn seed(mut self, seed: i64) -> Request {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Sets the number of choices to generate for the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `n` - The number of choices returned in the response.
+    pub fn n(mut self, n: i64) -> Request {
+        self.n = Some(n);
+        self
+    }
+
+    /// Requests the log probabilities of the generated tokens.
+    ///
+    /// ## Arguments
+    ///
+    /// * `top_logprobs` - The number of most likely alternatives to return for every
+    ///   token, if any.
+    pub fn logprobs(mut self, top_logprobs: Option<i64>) -> Request {
+        self.logprobs = Some(true);
+        self.top_logprobs = top_logprobs;
+        self
+    }
+
     /// Sets the input parameters for the request, to check for prompt injection and PII.
     ///
     /// ## Arguments
@@ -150,12 +244,30 @@ impl Request {
     }
 }
 
+/// The log probabilities of the tokens generated for a choice, in the format of the
+/// completions endpoint. The lists have one entry per token, in order.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Logprobs {
+    pub tokens: Vec<String>,
+    /// The log probability of each token, `None` for tokens of the prompt that have
+    /// none.
+    pub token_logprobs: Vec<Option<f64>>,
+    /// The most likely tokens at each position with their log probabilities.
+    pub top_logprobs: Vec<Option<HashMap<String, f64>>>,
+    /// The offset of each token in the generated text.
+    pub text_offset: Vec<usize>,
+}
+
 /// Represents a choice in the base completion response.
 #[derive(Debug, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Choice {
     pub text: String,
     pub index: i64,
+    pub finish_reason: Option<FinishReason>,
+    #[serde(deserialize_with = "deserialize_logprobs")]
+    pub logprobs: Option<Logprobs>,
 }
 
 /// Completion response for the base completetion endpoint.
@@ -168,3 +280,29 @@ pub struct Response {
     pub created: i64,
     pub choices: Vec<Choice>,
 }
+
+/// Represents a choice in a completion events response. The `text` holds the part of
+/// the completion generated since the previous event.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ChoiceEvents {
+    pub text: String,
+    pub index: i64,
+    pub generated_text: Option<String>,
+    #[serde(deserialize_with = "deserialize_logprobs")]
+    pub logprobs: Option<Logprobs>,
+    pub finish_reason: Option<FinishReason>,
+}
+
+/// Completion response returned from the completion events endpoint.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ResponseEvents {
+    pub id: String,
+    pub object: String,
+    pub created: i64,
+    pub model: String,
+    pub choices: Vec<ChoiceEvents>,
+    #[serde(deserialize_with = "crate::chat::deserialize_error")]
+    pub error: Option<String>,
+}
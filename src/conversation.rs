@@ -0,0 +1,942 @@
+//! Conversation that keeps the history of a chat and truncates it to the context
+//! length of the model.
+//!
+//! Every message is counted once, with the tokenize endpoint or an estimate, and the
+//! count is cached with the message. Before a request is sent the oldest turns are
+//! dropped, or summarised, until the conversation and the tokens reserved for the
+//! response fit in the `max_context_length` of the model. The system prompt and the
+//! latest turn are always kept.
+//!
+//! A conversation can be saved to a versioned JSON format with [`Conversation::to_json`]
+//! and resumed with [`Conversation::from_json`]. It can also be exported to, and
+//! imported from, the OpenAI chat message format.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{client, conversation::{Conversation, Truncation}};
+//!
+//! let clt = client::Client::new()?;
+//!
+//! let mut conv = Conversation::new(clt, "Hermes-3-Llama-3.1-70B".to_string())
+//!     .system_prompt("You are a helpful assistant.".to_string())
+//!     .max_tokens(500)
+//!     .truncation(Truncation::Summarize);
+//!
+//! let resp = conv.send("What is the capital of France?".to_string()).await?;
+//! let resp = conv.send("And of Germany?".to_string()).await?;
+//! ```
+use std::collections::BTreeMap;
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{chat, client::Client, tokenize, PgError, Result};
+
+/// The version of the JSON format written by [`Conversation::to_json`].
+pub const FORMAT_VERSION: u32 = 1;
+
+/// The number of tokens reserved for the response when max tokens is not set.
+pub const DEFAULT_RESERVED_TOKENS: usize = 512;
+
+const SUMMARY_MAX_TOKENS: i64 = 256;
+
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+const SUMMARY_PROMPT: &str = "Summarize the following conversation in a few sentences. Keep the facts, names and decisions that are needed to continue the conversation. Respond only with the summary.";
+
+/// How the oldest turns are removed when the conversation is too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// The oldest turns are dropped.
+    #[default]
+    Drop,
+    /// The oldest turns are replaced with a summary written by the model, which is sent
+    /// after the system prompt.
+    Summarize,
+}
+
+/// How the tokens of a message are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenCounter {
+    /// Calls the tokenize endpoint with the model of the conversation.
+    #[default]
+    Tokenize,
+    /// Estimates four characters per token, for models without the tokenize capability.
+    Estimate,
+}
+
+/// A message with its cached token count.
+#[derive(Debug, Clone)]
+struct Entry {
+    message: chat::Message,
+    tokens: Option<usize>,
+}
+
+impl Entry {
+    fn new(message: chat::Message) -> Self {
+        Self {
+            message,
+            tokens: None,
+        }
+    }
+}
+
+/// The saved state of a conversation, written as JSON by [`Conversation::to_json`].
+///
+/// The format is versioned with [`FORMAT_VERSION`]. Token counts are not saved, they
+/// are counted again when the conversation is resumed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The version of the format.
+    pub version: u32,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// The summary of the truncated turns.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// The messages after the system prompt and the summary.
+    #[serde(default)]
+    pub messages: Vec<chat::Message>,
+    #[serde(default)]
+    pub parameters: Parameters,
+    /// Application data saved with the conversation.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, serde_json::Value>,
+}
+
+/// The request parameters and truncation settings of a saved conversation.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Parameters {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_context_length: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<i64>,
+    pub truncation: Truncation,
+    pub token_counter: TokenCounter,
+}
+
+/// A chat session that tracks the messages and keeps them within the context length.
+#[derive(Debug, Clone)]
+pub struct Conversation {
+    client: Client,
+    model: String,
+    system: Option<Entry>,
+    summary: Option<Entry>,
+    history: Vec<Entry>,
+    max_context_length: Option<usize>,
+    max_tokens: Option<i64>,
+    temperature: Option<f64>,
+    top_p: Option<f64>,
+    top_k: Option<i64>,
+    truncation: Truncation,
+    counter: TokenCounter,
+    metadata: BTreeMap<String, serde_json::Value>,
+}
+
+impl Conversation {
+    /// Creates a new conversation without messages.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the API.
+    /// * `model` - The model to be used for the requests.
+    pub fn new(client: Client, model: String) -> Self {
+        Self {
+            client,
+            model,
+            system: None,
+            summary: None,
+            history: Vec::new(),
+            max_context_length: None,
+            max_tokens: None,
+            temperature: None,
+            top_p: None,
+            top_k: None,
+            truncation: Truncation::default(),
+            counter: TokenCounter::default(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the system prompt, which is never truncated.
+    ///
+    /// ## Arguments
+    ///
+    /// * `prompt` - The system prompt.
+    pub fn system_prompt(mut self, prompt: String) -> Self {
+        self.system = Some(Entry::new(chat::Message::new(chat::Roles::System, prompt)));
+        self
+    }
+
+    /// Sets the context length of the model. When it is not set it is loaded from the
+    /// models endpoint before the first request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `max` - The maximum number of tokens of the conversation and the response.
+    pub fn max_context_length(mut self, max: usize) -> Self {
+        self.max_context_length = Some(max);
+        self
+    }
+
+    /// Sets the max tokens of the responses. The same number of tokens is reserved in
+    /// the context, [`DEFAULT_RESERVED_TOKENS`] are reserved when it is not set.
+    ///
+    /// ## Arguments
+    ///
+    /// * `max` - The maximum number of tokens to be returned in a response.
+    pub fn max_tokens(mut self, max: i64) -> Self {
+        self.max_tokens = Some(max);
+        self
+    }
+
+    /// Sets the temperature of the requests.
+    ///
+    /// ## Arguments
+    ///
+    /// * `temp` - The temperature setting for the requests. Used to control randomness.
+    pub fn temperature(mut self, temp: f64) -> Self {
+        self.temperature = Some(temp);
+        self
+    }
+
+    /// Sets the top p of the requests.
+    ///
+    /// ## Arguments
+    ///
+    /// * `top` - The diversity of the generated text based on nucleus sampling.
+    pub fn top_p(mut self, top: f64) -> Self {
+        self.top_p = Some(top);
+        self
+    }
+
+    /// Sets the top k of the requests.
+    ///
+    /// ## Arguments
+    ///
+    /// * `top_k` - The diversity of the generated text based on top-k sampling.
+    pub fn top_k(mut self, top_k: i64) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Sets how the oldest turns are removed. The default is [`Truncation::Drop`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `truncation` - The truncation strategy.
+    pub fn truncation(mut self, truncation: Truncation) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    /// Sets how the tokens are counted. The default is [`TokenCounter::Tokenize`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `counter` - The token counter.
+    pub fn token_counter(mut self, counter: TokenCounter) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    /// Returns the model of the conversation.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Returns the application data saved with the conversation.
+    pub fn metadata(&self) -> &BTreeMap<String, serde_json::Value> {
+        &self.metadata
+    }
+
+    /// Returns the application data saved with the conversation, to be changed.
+    pub fn metadata_mut(&mut self) -> &mut BTreeMap<String, serde_json::Value> {
+        &mut self.metadata
+    }
+
+    /// Returns the summary of the truncated turns, if there is one.
+    pub fn summary(&self) -> Option<&str> {
+        self.summary
+            .as_ref()
+            .and_then(|e| e.message.content.as_str())
+            .map(|s| s.strip_prefix(SUMMARY_PREFIX).unwrap_or(s))
+    }
+
+    /// Returns the messages of the conversation after the system prompt and summary.
+    pub fn history(&self) -> Vec<&chat::Message> {
+        self.history.iter().map(|e| &e.message).collect()
+    }
+
+    /// Returns the messages sent to the model: the system prompt, the summary and the
+    /// history.
+    pub fn messages(&self) -> Vec<chat::Message> {
+        self.system
+            .iter()
+            .chain(self.summary.iter())
+            .chain(self.history.iter())
+            .map(|e| e.message.clone())
+            .collect()
+    }
+
+    /// Returns the number of turns, a turn starts with a user message.
+    pub fn turns(&self) -> usize {
+        self.history
+            .iter()
+            .filter(|e| e.message.role == chat::Roles::User)
+            .count()
+    }
+
+    /// Adds a message to the end of the conversation.
+    ///
+    /// ## Arguments
+    ///
+    /// * `message` - The message to add.
+    pub fn push(&mut self, message: chat::Message) {
+        self.history.push(Entry::new(message));
+    }
+
+    /// Adds the message of the first choice of a response as an assistant message.
+    ///
+    /// ## Arguments
+    ///
+    /// * `resp` - The response returned from the model.
+    pub fn add_response(&mut self, resp: &chat::Response) {
+        if let Some(choice) = resp.choices.first() {
+            let mut msg = choice.message.clone();
+            msg.role = chat::Roles::Assistant;
+            self.push(msg);
+        }
+    }
+
+    /// Removes the history and the summary, the system prompt is kept.
+    pub fn clear(&mut self) {
+        self.summary = None;
+        self.history.clear();
+    }
+
+    /// Returns a chat request with the messages of the conversation.
+    pub fn request(&self) -> chat::Request<chat::Message> {
+        let mut req =
+            chat::Request::<chat::Message>::new(self.model.clone()).with_messages(self.messages());
+
+        if let Some(max) = self.max_tokens {
+            req = req.max_tokens(max);
+        }
+        if let Some(temp) = self.temperature {
+            req = req.temperature(temp);
+        }
+        if let Some(top) = self.top_p {
+            req = req.top_p(top);
+        }
+        if let Some(top_k) = self.top_k {
+            req = req.top_k(top_k);
+        }
+
+        req
+    }
+
+    /// Returns the saved state of the conversation.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: FORMAT_VERSION,
+            model: self.model.clone(),
+            system_prompt: self
+                .system
+                .as_ref()
+                .map(|e| e.message.content.text().into_owned()),
+            summary: self.summary().map(|s| s.to_string()),
+            messages: self.history.iter().map(|e| e.message.clone()).collect(),
+            parameters: Parameters {
+                max_context_length: self.max_context_length,
+                max_tokens: self.max_tokens,
+                temperature: self.temperature,
+                top_p: self.top_p,
+                top_k: self.top_k,
+                truncation: self.truncation,
+                token_counter: self.counter,
+            },
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Resumes a conversation from its saved state.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the API.
+    /// * `snapshot` - The saved state of the conversation.
+    ///
+    /// Returns an error if the snapshot was written by a newer version of the format.
+    pub fn from_snapshot(client: Client, snapshot: Snapshot) -> Result<Self> {
+        check_version(snapshot.version)?;
+
+        let params = snapshot.parameters;
+
+        Ok(Self {
+            client,
+            model: snapshot.model,
+            system: snapshot
+                .system_prompt
+                .map(|p| Entry::new(chat::Message::new(chat::Roles::System, p))),
+            summary: snapshot.summary.map(summary_entry),
+            history: snapshot.messages.into_iter().map(Entry::new).collect(),
+            max_context_length: params.max_context_length,
+            max_tokens: params.max_tokens,
+            temperature: params.temperature,
+            top_p: params.top_p,
+            top_k: params.top_k,
+            truncation: params.truncation,
+            counter: params.token_counter,
+            metadata: snapshot.metadata,
+        })
+    }
+
+    /// Returns the conversation as versioned JSON, see [`Snapshot`].
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.snapshot())?)
+    }
+
+    /// Resumes a conversation saved with [`Conversation::to_json`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the API.
+    /// * `json` - The saved conversation.
+    ///
+    /// Returns a [`PgError::Conversation`] if the version is missing or not supported,
+    /// or if the JSON is not a valid snapshot.
+    pub fn from_json(client: Client, json: &str) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        // Check the version first, a newer format may not parse into the snapshot.
+        let version = serde_json::from_str::<Version>(json)
+            .map_err(|e| PgError::Conversation(format!("missing or invalid version: {}", e)))?;
+        check_version(version.version)?;
+
+        let snapshot = serde_json::from_str(json)
+            .map_err(|e| PgError::Conversation(format!("invalid snapshot: {}", e)))?;
+
+        Self::from_snapshot(client, snapshot)
+    }
+
+    /// Returns the messages sent to the model as an OpenAI chat message array. The
+    /// summary is sent as a second system message.
+    pub fn to_openai(&self) -> serde_json::Value {
+        to_openai_messages(&self.messages())
+    }
+
+    /// Creates a conversation from an OpenAI chat message array. A leading system
+    /// message is used as the system prompt, and a following system message written by
+    /// [`Conversation::to_openai`] as the summary.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the API.
+    /// * `model` - The model to be used for the requests.
+    /// * `messages` - The OpenAI chat messages.
+    pub fn from_openai(
+        client: Client,
+        model: String,
+        messages: &serde_json::Value,
+    ) -> Result<Self> {
+        let mut messages = from_openai_messages(messages)?.into_iter().peekable();
+        let mut conv = Self::new(client, model);
+
+        if let Some(msg) = messages.next_if(|m| m.role == chat::Roles::System) {
+            conv.system = Some(Entry::new(msg));
+        }
+
+        if let Some(msg) = messages.next_if(|m| {
+            m.role == chat::Roles::System
+                && m.content
+                    .as_str()
+                    .is_some_and(|s| s.starts_with(SUMMARY_PREFIX))
+        }) {
+            conv.summary = Some(Entry::new(msg));
+        }
+
+        conv.history = messages.map(Entry::new).collect();
+
+        Ok(conv)
+    }
+
+    /// Adds a user message, truncates the conversation and calls the chat completion
+    /// endpoint. The response is added to the conversation. If the truncation or the
+    /// request fails, the conversation is left unchanged so the turn can be sent again.
+    ///
+    /// ## Arguments
+    ///
+    /// * `prompt` - The user message.
+    pub async fn send(&mut self, prompt: String) -> Result<chat::Response> {
+        let history = self.history.clone();
+        let summary = self.summary.clone();
+
+        self.push(chat::Message::new(chat::Roles::User, prompt));
+
+        let resp = match self.complete().await {
+            Ok(resp) => resp,
+            Err(err) => {
+                self.history = history;
+                self.summary = summary;
+                return Err(err);
+            }
+        };
+        self.add_response(&resp);
+
+        Ok(resp)
+    }
+
+    async fn complete(&mut self) -> Result<chat::Response> {
+        self.truncate().await?;
+
+        self.client.generate_chat_completion(&self.request()).await
+    }
+
+    /// Returns the number of tokens of the messages sent to the model. Messages that
+    /// were not counted yet are counted and cached.
+    pub async fn token_count(&mut self) -> Result<usize> {
+        let mut entries: Vec<&mut Entry> = self
+            .system
+            .iter_mut()
+            .chain(self.summary.iter_mut())
+            .chain(self.history.iter_mut())
+            .collect();
+
+        let mut total = 0;
+        for entry in entries.iter_mut() {
+            total += count(&self.client, &self.model, self.counter, entry).await?;
+        }
+
+        Ok(total)
+    }
+
+    /// Removes the oldest turns until the conversation fits in the context length with
+    /// the tokens reserved for the response. The system prompt and the latest turn are
+    /// always kept, even if they do not fit.
+    pub async fn truncate(&mut self) -> Result<()> {
+        let max_context = self.context_length().await?;
+        let reserved = self
+            .max_tokens
+            .map(|x| x.max(0) as usize)
+            .unwrap_or(DEFAULT_RESERVED_TOKENS);
+        let budget = max_context.saturating_sub(reserved);
+
+        loop {
+            let mut total = self.token_count().await?;
+            if total <= budget {
+                return Ok(());
+            }
+
+            let mut dropped = Vec::new();
+            while total > budget {
+                let end = match turn_end(&self.history) {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                for entry in self.history.drain(..end) {
+                    total -= entry.tokens.unwrap_or(0);
+                    dropped.push(entry.message);
+                }
+            }
+
+            if dropped.is_empty() {
+                warn!(
+                    "conversation - {} tokens do not fit in the context of {} tokens",
+                    total, budget
+                );
+                return Ok(());
+            }
+
+            if self.truncation == Truncation::Summarize {
+                let summary = self.summarize(&dropped).await?;
+                self.summary = Some(summary_entry(summary));
+            }
+        }
+    }
+
+    async fn context_length(&mut self) -> Result<usize> {
+        if let Some(max) = self.max_context_length {
+            return Ok(max);
+        }
+
+        let max = self
+            .client
+            .model_info(&self.model)
+            .await?
+            .map(|m| m.max_context_length.max(0) as usize)
+            .ok_or_else(|| {
+                PgError::Config(format!("model {} not found in the model list", self.model))
+            })?;
+
+        self.max_context_length = Some(max);
+        Ok(max)
+    }
+
+    async fn summarize(&self, dropped: &[chat::Message]) -> Result<String> {
+        let mut transcript = String::new();
+
+        if let Some(summary) = self.summary() {
+            transcript.push_str(summary);
+            transcript.push_str("\n\n");
+        }
+        for msg in dropped {
+            transcript.push_str(&format!(
+                "{}: {}\n",
+                role_name(&msg.role),
+                msg.content.text()
+            ));
+        }
+
+        let req = chat::Request::<chat::Message>::new(self.model.clone())
+            .add_message(chat::Roles::System, SUMMARY_PROMPT.to_string())
+            .add_message(chat::Roles::User, transcript)
+            .max_tokens(SUMMARY_MAX_TOKENS);
+
+        let resp = self.client.generate_chat_completion(&req).await?;
+
+        Ok(resp
+            .choices
+            .into_iter()
+            .next()
+            .map(|c| c.message.content.into_text().trim().to_string())
+            .unwrap_or_default())
+    }
+}
+
+/// Returns the messages as an OpenAI chat message array.
+///
+/// ## Arguments
+///
+/// * `messages` - The messages to convert.
+pub fn to_openai_messages(messages: &[chat::Message]) -> serde_json::Value {
+    serde_json::Value::Array(
+        messages
+            .iter()
+            .map(|m| serde_json::to_value(m).unwrap_or_default())
+            .collect(),
+    )
+}
+
+/// Parses an OpenAI chat message array. The `developer` role is read as a system
+/// message and the legacy `function` role as a tool message. Fields that are not used
+/// by the API, e.g. `name`, are ignored.
+///
+/// ## Arguments
+///
+/// * `messages` - The OpenAI chat messages.
+///
+/// Returns a [`PgError::Conversation`] if the value is not an array of messages, e.g.
+/// with an unknown role.
+pub fn from_openai_messages(messages: &serde_json::Value) -> Result<Vec<chat::Message>> {
+    let items = messages
+        .as_array()
+        .ok_or_else(|| PgError::Conversation("expected an array of messages".to_string()))?;
+
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let mut item = item.clone();
+
+            if let Some(role) = item.get_mut("role") {
+                match role.as_str() {
+                    Some("developer") => *role = "system".into(),
+                    Some("function") => *role = "tool".into(),
+                    _ => {}
+                }
+            }
+
+            serde_json::from_value(item)
+                .map_err(|e| PgError::Conversation(format!("invalid message {}: {}", i, e)))
+        })
+        .collect()
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(PgError::Conversation(format!(
+            "unsupported conversation format version {}, expected {}",
+            version, FORMAT_VERSION
+        )));
+    }
+
+    Ok(())
+}
+
+fn summary_entry(summary: String) -> Entry {
+    Entry::new(chat::Message::new(
+        chat::Roles::System,
+        format!("{}{}", SUMMARY_PREFIX, summary),
+    ))
+}
+
+/// Returns the number of tokens of the entry, counting them if they are not cached.
+async fn count(
+    client: &Client,
+    model: &str,
+    counter: TokenCounter,
+    entry: &mut Entry,
+) -> Result<usize> {
+    if let Some(tokens) = entry.tokens {
+        return Ok(tokens);
+    }
+
+    let text = entry.message.content.text();
+
+    let tokens = match counter {
+        _ if text.is_empty() => 0,
+        TokenCounter::Tokenize => {
+            let req = tokenize::Request::new(model.to_string(), text.into_owned());
+            client.tokenize(&req).await?.tokens.len()
+        }
+        TokenCounter::Estimate => tokenize::estimate(&text),
+    };
+
+    let tokens = tokens + tokenize::MESSAGE_OVERHEAD;
+    entry.tokens = Some(tokens);
+
+    Ok(tokens)
+}
+
+/// Returns the end of the oldest turn, which is the index of the next user message.
+/// Returns `None` if the history has a single turn.
+fn turn_end(history: &[Entry]) -> Option<usize> {
+    history
+        .iter()
+        .skip(1)
+        .position(|e| e.message.role == chat::Roles::User)
+        .map(|i| i + 1)
+}
+
+fn role_name(role: &chat::Roles) -> &'static str {
+    match role {
+        chat::Roles::System => "system",
+        chat::Roles::User => "user",
+        chat::Roles::Assistant => "assistant",
+        chat::Roles::Tool => "tool",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client() -> Client {
+        Client::builder()
+            .api_key("api-key".to_string())
+            .host("http://localhost:8080".to_string())
+            .build()
+            .expect("client value")
+    }
+
+    fn conversation() -> Conversation {
+        let mut conv = Conversation::new(client(), "Hermes-3-Llama-3.1-70B".to_string())
+            .system_prompt("You are a helpful assistant.".to_string())
+            .max_context_length(8192)
+            .max_tokens(500)
+            .temperature(0.2)
+            .top_p(0.9)
+            .top_k(40)
+            .truncation(Truncation::Summarize)
+            .token_counter(TokenCounter::Estimate);
+
+        conv.summary = Some(summary_entry("The user lives in Paris.".to_string()));
+        conv.metadata_mut()
+            .insert("user_id".to_string(), serde_json::json!("user-42"));
+
+        conv.push(chat::Message::with_parts(
+            chat::Roles::User,
+            vec![
+                chat::Content::text("What is in this image?".to_string()),
+                chat::Content::image_url_with_detail(
+                    "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    chat::ImageDetail::Low,
+                ),
+            ],
+        ));
+        conv.push(chat::Message {
+            role: chat::Roles::Assistant,
+            tool_calls: vec![chat::ToolCall {
+                id: "call-1".to_string(),
+                tool_type: "function".to_string(),
+                function: chat::FunctionCall {
+                    name: "describe_image".to_string(),
+                    arguments: r#"{"detail":"low"}"#.to_string(),
+                },
+            }],
+            ..Default::default()
+        });
+        conv.push(chat::Message {
+            role: chat::Roles::Tool,
+            content: "A cat on a sofa.".into(),
+            tool_call_id: Some("call-1".to_string()),
+            ..Default::default()
+        });
+        conv.push(chat::Message::new(
+            chat::Roles::Assistant,
+            "The image shows a cat on a sofa.".to_string(),
+        ));
+
+        conv
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let conv = conversation();
+
+        let json = conv.to_json().expect("json");
+        let value: serde_json::Value = serde_json::from_str(&json).expect("value");
+        assert_eq!(value["version"], FORMAT_VERSION);
+        assert_eq!(value["summary"], "The user lives in Paris.");
+        assert_eq!(value["parameters"]["truncation"], "summarize");
+        assert_eq!(value["parameters"]["token_counter"], "estimate");
+        assert_eq!(value["metadata"]["user_id"], "user-42");
+
+        let loaded = Conversation::from_json(client(), &json).expect("load");
+
+        assert_eq!(loaded.snapshot(), conv.snapshot());
+        assert_eq!(loaded.messages(), conv.messages());
+        assert_eq!(loaded.summary(), Some("The user lives in Paris."));
+        assert_eq!(loaded.turns(), 1);
+
+        let req = serde_json::to_value(loaded.request()).expect("request");
+        assert_eq!(req["top_k"], 40);
+        assert_eq!(req["messages"].as_array().map(|m| m.len()), Some(6));
+    }
+
+    #[test]
+    fn json_versions() {
+        let minimal = r#"{"version":1,"model":"Hermes-3-Llama-3.1-70B"}"#;
+        let conv = Conversation::from_json(client(), minimal).expect("minimal");
+        assert!(conv.messages().is_empty());
+        assert_eq!(conv.snapshot().parameters, Parameters::default());
+
+        let err = Conversation::from_json(client(), r#"{"version":2,"model":{"id":"x"}}"#)
+            .expect_err("newer version");
+        assert!(matches!(err, PgError::Conversation(_)), "{:?}", err);
+        assert!(
+            err.to_string()
+                .contains("unsupported conversation format version 2"),
+            "{}",
+            err
+        );
+
+        let err = Conversation::from_json(client(), r#"{"model":"x"}"#).expect_err("no version");
+        assert!(matches!(err, PgError::Conversation(_)), "{:?}", err);
+        assert!(
+            err.to_string().contains("missing or invalid version"),
+            "{}",
+            err
+        );
+
+        let err = Conversation::from_json(client(), r#"{"version":1,"model":{"id":"x"}}"#)
+            .expect_err("invalid shape");
+        assert!(matches!(err, PgError::Conversation(_)), "{:?}", err);
+        assert!(err.to_string().contains("invalid snapshot"), "{}", err);
+    }
+
+    #[test]
+    fn openai_round_trip() {
+        let conv = conversation();
+
+        let openai = conv.to_openai();
+        assert_eq!(openai[0]["role"], "system");
+        assert_eq!(
+            openai[1]["content"],
+            "Summary of the earlier conversation: The user lives in Paris."
+        );
+        assert_eq!(openai[2]["content"][1]["image_url"]["detail"], "low");
+        assert_eq!(
+            openai[3]["tool_calls"][0]["function"]["name"],
+            "describe_image"
+        );
+        assert_eq!(openai[4]["tool_call_id"], "call-1");
+
+        let imported =
+            Conversation::from_openai(client(), "Hermes-3-Llama-3.1-70B".to_string(), &openai)
+                .expect("import");
+
+        assert_eq!(imported.messages(), conv.messages());
+        assert_eq!(imported.summary(), Some("The user lives in Paris."));
+        assert_eq!(imported.to_openai(), openai);
+    }
+
+    #[test]
+    fn openai_import() {
+        let messages = serde_json::json!([
+            {"role": "developer", "content": "Answer briefly."},
+            {"role": "user", "name": "ed", "content": [{"type": "text", "text": "Weather in Paris?"}]},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call-1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}
+            ]},
+            {"role": "function", "tool_call_id": "call-1", "content": "sunny"},
+            {"role": "assistant", "content": "It is sunny."}
+        ]);
+
+        let conv =
+            Conversation::from_openai(client(), "Hermes-3-Llama-3.1-70B".to_string(), &messages)
+                .expect("import");
+
+        let imported = conv.messages();
+        assert_eq!(imported.len(), 5);
+        assert_eq!(imported[0].role, chat::Roles::System);
+        assert_eq!(conv.summary(), None);
+        assert_eq!(imported[1].content.text(), "Weather in Paris?");
+        assert!(imported[2].content.is_empty());
+        assert_eq!(imported[2].tool_calls[0].function.name, "get_weather");
+        assert_eq!(imported[3].role, chat::Roles::Tool);
+        assert_eq!(conv.turns(), 1);
+
+        let err =
+            from_openai_messages(&serde_json::json!({"role": "user"})).expect_err("not an array");
+        assert!(matches!(err, PgError::Conversation(_)), "{:?}", err);
+
+        let err = from_openai_messages(&serde_json::json!([
+            {"role": "user", "content": "Hi"},
+            {"role": "critic", "content": "x"}
+        ]))
+        .expect_err("unknown role");
+        assert!(matches!(err, PgError::Conversation(_)), "{:?}", err);
+        assert!(err.to_string().contains("invalid message 1"), "{}", err);
+    }
+
+    fn entries(roles: &[chat::Roles]) -> Vec<Entry> {
+        roles
+            .iter()
+            .map(|r| Entry::new(chat::Message::new(r.clone(), "text".to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn turn_boundaries() {
+        use chat::Roles::*;
+
+        assert_eq!(
+            turn_end(&entries(&[User, Assistant, User, Assistant])),
+            Some(2)
+        );
+        assert_eq!(
+            turn_end(&entries(&[User, Assistant, Tool, Assistant, User])),
+            Some(4)
+        );
+        assert_eq!(turn_end(&entries(&[Assistant, User])), Some(1));
+        assert_eq!(turn_end(&entries(&[User, Assistant])), None);
+        assert_eq!(turn_end(&entries(&[])), None);
+    }
+}
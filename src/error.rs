@@ -0,0 +1,231 @@
+//! Error type returned from the Prediction Guard client.
+use std::{error, fmt, path::PathBuf};
+
+use reqwest::StatusCode;
+
+use crate::guard::Reason;
+
+/// The errors that can be returned from the client calls.
+///
+/// The error is `Send + Sync` so it can be moved across `tokio::spawn` boundaries.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum PgError {
+    /// The request could not be sent or the response body could not be read.
+    Transport(reqwest::Error),
+    /// The request timed out before it could complete.
+    Timeout(reqwest::Error),
+    /// The API returned a status code other than 200 (Ok).
+    Http {
+        /// The status code returned from the API.
+        status: StatusCode,
+        /// The error message parsed from the response body, if it could be parsed.
+        message: Option<String>,
+        /// The raw response body.
+        body: String,
+    },
+    /// The request or response body could not be (de)serialized.
+    Deserialize(serde_json::Error),
+    /// An error occurred while receiving a streamed response.
+    Stream(String),
+    /// The client configuration is invalid.
+    Config(String),
+    /// The model did not return output that could be parsed into the requested type.
+    Structured {
+        /// The number of requests made to the model.
+        attempts: u32,
+        /// The error from parsing the last response.
+        message: String,
+        /// The content of the last response.
+        content: String,
+    },
+    /// The image is too large or its format is not supported.
+    Image(String),
+    /// A file or reader could not be read.
+    Io(std::io::Error),
+    /// The request is not supported by the model, found before it was sent.
+    Validation(String),
+    /// The API returned a response that is missing data, e.g. without choices.
+    Response(String),
+    /// A saved conversation has an unsupported format version or an invalid shape.
+    Conversation(String),
+    /// A guardrail policy could not be parsed or is invalid.
+    Policy {
+        /// The file the policy was loaded from.
+        path: Option<PathBuf>,
+        /// The line of the error, starting at 1.
+        line: Option<usize>,
+        /// The column of the error, starting at 1.
+        column: Option<usize>,
+        /// The error message from the parser or the validation.
+        message: String,
+    },
+    /// A streamed output was stopped by a moderation check, see
+    /// [`crate::moderation::Moderator`].
+    Moderation {
+        /// The check that failed.
+        reason: Reason,
+        /// The score that failed the check, `None` for PII.
+        score: Option<f64>,
+        /// The index of the choice that was stopped.
+        index: i64,
+    },
+}
+
+impl PgError {
+    /// Returns the status code when the error was caused by a non 200 (Ok) response.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            PgError::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Returns the error message sent by the API, if there is one.
+    pub fn api_message(&self) -> Option<&str> {
+        match self {
+            PgError::Http { message, .. } => message.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the error was caused by a timeout.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, PgError::Timeout(_))
+    }
+}
+
+impl fmt::Display for PgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PgError::Transport(e) => write!(f, "transport error: {}", e),
+            PgError::Timeout(e) => write!(f, "request timed out: {}", e),
+            PgError::Http {
+                status,
+                message: Some(msg),
+                ..
+            } => write!(f, "api error {}: {}", status, msg),
+            PgError::Http { status, body, .. } => write!(f, "api error {}: {}", status, body),
+            PgError::Deserialize(e) => write!(f, "error parsing response: {}", e),
+            PgError::Stream(msg) => write!(f, "stream error: {}", msg),
+            PgError::Config(msg) => write!(f, "invalid configuration: {}", msg),
+            PgError::Structured {
+                attempts, message, ..
+            } => write!(
+                f,
+                "invalid structured output after {} attempts: {}",
+                attempts, message
+            ),
+            PgError::Image(msg) => write!(f, "invalid image: {}", msg),
+            PgError::Io(e) => write!(f, "io error: {}", e),
+            PgError::Validation(msg) => write!(f, "invalid request: {}", msg),
+            PgError::Response(msg) => write!(f, "invalid response: {}", msg),
+            PgError::Conversation(msg) => write!(f, "invalid conversation: {}", msg),
+            PgError::Policy {
+                path,
+                line,
+                column,
+                message,
+            } => {
+                write!(f, "invalid policy")?;
+                if let Some(path) = path {
+                    write!(f, " {}", path.display())?;
+                }
+                if let (Some(line), Some(column)) = (line, column) {
+                    write!(f, " at line {} column {}", line, column)?;
+                }
+                write!(f, ": {}", message)
+            }
+            PgError::Moderation {
+                reason,
+                score: Some(score),
+                ..
+            } => write!(f, "output blocked by the {} check, score {}", reason, score),
+            PgError::Moderation { reason, .. } => {
+                write!(f, "output blocked by the {} check", reason)
+            }
+        }
+    }
+}
+
+impl error::Error for PgError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PgError::Transport(e) | PgError::Timeout(e) => Some(e),
+            PgError::Deserialize(e) => Some(e),
+            PgError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for PgError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            PgError::Timeout(e)
+        } else {
+            PgError::Transport(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for PgError {
+    fn from(e: serde_json::Error) -> Self {
+        PgError::Deserialize(e)
+    }
+}
+
+impl From<std::io::Error> for PgError {
+    fn from(e: std::io::Error) -> Self {
+        PgError::Io(e)
+    }
+}
+
+impl From<reqwest::header::InvalidHeaderValue> for PgError {
+    fn from(e: reqwest::header::InvalidHeaderValue) -> Self {
+        PgError::Config(format!("invalid header value, {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    #[test]
+    fn error_is_send_sync() {
+        assert_send_sync::<PgError>();
+    }
+
+    #[test]
+    fn http_error() {
+        let err = PgError::Http {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: Some("rate limited".to_string()),
+            body: r#"{"error":"rate limited"}"#.to_string(),
+        };
+
+        assert_eq!(err.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert_eq!(err.api_message(), Some("rate limited"));
+        assert!(!err.is_timeout());
+        assert_eq!(err.to_string(), "api error 429 Too Many Requests: rate limited");
+    }
+
+    #[test]
+    fn moderation_error() {
+        let err = PgError::Moderation {
+            reason: Reason::Toxicity,
+            score: Some(0.75),
+            index: 0,
+        };
+        assert_eq!(err.to_string(), "output blocked by the toxicity check, score 0.75");
+
+        let err = PgError::Moderation {
+            reason: Reason::Pii,
+            score: None,
+            index: 1,
+        };
+        assert_eq!(err.to_string(), "output blocked by the pii check");
+    }
+}
@@ -0,0 +1,394 @@
+//! Guardrail pipeline that combines the injection, PII, toxicity and factuality checks.
+//!
+//! A [`Guard`] runs the configured pre-checks on a prompt before it is sent to a model,
+//! and the configured post-checks on the output of the model. The checks of each stage
+//! are independent and run concurrently. Every check returns a [`Report`] with the
+//! [`Verdict`] and all of the scores that were computed, even if one of the checks
+//! blocked the text.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{client, guard, pii};
+//!
+//! let clt = client::Client::new()?;
+//!
+//! let guard = guard::Guard::new(clt)
+//!     .injection(0.5)
+//!     .pii(pii::InputMethod::Replace, pii::ReplaceMethod::Category)
+//!     .toxicity(0.7)
+//!     .factuality(vec!["The sky is blue.".to_string()], 0.5);
+//!
+//! let report = guard.check_prompt("My email is jane@example.com".to_string()).await?;
+//!
+//! match report.verdict {
+//!     guard::Verdict::Allowed => println!("prompt is allowed"),
+//!     guard::Verdict::Redacted(prompt) => println!("send instead: {}", prompt),
+//!     guard::Verdict::Blocked { reason, score } => println!("blocked: {} {:?}", reason, score),
+//! }
+//! ```
+use std::fmt;
+
+use futures::future;
+#[cfg(feature = "local-pii")]
+use log::warn;
+
+use crate::{client::Client, factuality, injection, pii, toxicity, PgError, Result};
+
+/// The check that blocked the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The probability of a prompt injection reached the threshold.
+    Injection,
+    /// The text contains PII that is blocked instead of replaced.
+    Pii,
+    /// The toxicity score of the output reached the threshold.
+    Toxicity,
+    /// The factuality score of the output is below the threshold for every reference.
+    Factuality,
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Reason::Injection => "injection",
+            Reason::Pii => "pii",
+            Reason::Toxicity => "toxicity",
+            Reason::Factuality => "factuality",
+        };
+
+        f.write_str(s)
+    }
+}
+
+/// The decision of the guard for a prompt or an output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// The text passed all of the checks.
+    Allowed,
+    /// The prompt contains PII that was replaced, the new prompt should be sent instead.
+    Redacted(String),
+    /// The text failed a check.
+    Blocked {
+        reason: Reason,
+        /// The score that failed the check, `None` for PII.
+        score: Option<f64>,
+    },
+}
+
+impl Verdict {
+    /// Returns true if the text was blocked.
+    pub fn is_blocked(&self) -> bool {
+        matches!(self, Verdict::Blocked { .. })
+    }
+}
+
+/// The scores of the checks that were run, `None` if a check is not configured.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Scores {
+    /// The probability of a prompt injection.
+    pub injection: Option<f64>,
+    /// True if PII was found in the prompt.
+    pub pii: Option<bool>,
+    /// The prompt with the PII replaced.
+    pub pii_prompt: Option<String>,
+    /// True if the PII endpoint could not be reached and the prompt was only checked
+    /// by the local detector, see `Guard::local_pii`.
+    pub pii_local: bool,
+    /// The toxicity score of the output.
+    pub toxicity: Option<f64>,
+    /// The factuality score of the output for each reference, in order.
+    pub factuality: Option<Vec<f64>>,
+}
+
+/// The result of a guard check.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub verdict: Verdict,
+    pub scores: Scores,
+}
+
+#[derive(Debug, Clone)]
+struct PiiCheck {
+    method: pii::InputMethod,
+    replace_method: pii::ReplaceMethod,
+}
+
+#[derive(Debug, Clone)]
+struct FactualityCheck {
+    references: Vec<String>,
+    threshold: f64,
+}
+
+/// Runs the configured checks on prompts and outputs. No checks are configured by
+/// default, so every text is allowed.
+#[derive(Debug, Clone)]
+pub struct Guard {
+    client: Client,
+    injection: Option<f64>,
+    pii: Option<PiiCheck>,
+    toxicity: Option<f64>,
+    factuality: Option<FactualityCheck>,
+    #[cfg(feature = "local-pii")]
+    local_pii: Option<pii::LocalDetector>,
+}
+
+impl Guard {
+    /// Creates a new guard without checks.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the check endpoints.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            injection: None,
+            pii: None,
+            toxicity: None,
+            factuality: None,
+            #[cfg(feature = "local-pii")]
+            local_pii: None,
+        }
+    }
+
+    /// Blocks prompts with a probability of a prompt injection of at least the threshold.
+    ///
+    /// ## Arguments
+    ///
+    /// * `threshold` - The probability from 0.0 to 1.0.
+    pub fn injection(mut self, threshold: f64) -> Self {
+        self.injection = Some(threshold);
+        self
+    }
+
+    /// Checks prompts for PII, which is either blocked or replaced.
+    ///
+    /// ## Arguments
+    ///
+    /// * `method` - Blocks the prompt or replaces the PII.
+    /// * `replace_method` - The method used to replace the PII.
+    pub fn pii(mut self, method: pii::InputMethod, replace_method: pii::ReplaceMethod) -> Self {
+        self.pii = Some(PiiCheck {
+            method,
+            replace_method,
+        });
+        self
+    }
+
+    /// Replaces the PII found by a local detector before the prompt is sent to the PII
+    /// endpoint, so it never leaves the process. If the endpoint can't be reached, or
+    /// returns a server error, the locally replaced prompt is used instead of failing
+    /// the check, and [`Scores::pii_local`] is set so callers can reject the prompt.
+    /// Only used when the PII check is configured.
+    ///
+    /// ## Arguments
+    ///
+    /// * `detector` - The detector used as a pre-filter and fallback.
+    #[cfg(feature = "local-pii")]
+    pub fn local_pii(mut self, detector: pii::LocalDetector) -> Self {
+        self.local_pii = Some(detector);
+        self
+    }
+
+    /// Blocks outputs with a toxicity score of at least the threshold.
+    ///
+    /// ## Arguments
+    ///
+    /// * `threshold` - The score from 0.0 to 1.0.
+    pub fn toxicity(mut self, threshold: f64) -> Self {
+        self.toxicity = Some(threshold);
+        self
+    }
+
+    /// Blocks outputs that are not supported by any of the references. An output is
+    /// allowed if its factuality score for at least one reference reaches the threshold.
+    ///
+    /// ## Arguments
+    ///
+    /// * `references` - The reference texts, each is checked with a separate request.
+    /// * `threshold` - The score from 0.0 to 1.0.
+    pub fn factuality(mut self, references: Vec<String>, threshold: f64) -> Self {
+        self.factuality = Some(FactualityCheck {
+            references,
+            threshold,
+        });
+        self
+    }
+
+    /// Runs the injection and PII checks on a prompt concurrently. An injection blocks
+    /// the prompt before PII is considered.
+    ///
+    /// ## Arguments
+    ///
+    /// * `prompt` - The prompt to check.
+    ///
+    /// Returns an error if any of the check requests fail.
+    pub async fn check_prompt(&self, prompt: String) -> Result<Report> {
+        let (injection, (pii_prompt, pii_local)) =
+            future::try_join(self.injection_score(&prompt), self.pii_prompt(&prompt)).await?;
+
+        let mut verdict = match (injection, self.injection) {
+            (Some(probability), Some(threshold)) if probability >= threshold => Verdict::Blocked {
+                reason: Reason::Injection,
+                score: Some(probability),
+            },
+            _ => Verdict::Allowed,
+        };
+
+        let found = pii_prompt.as_ref().map(|p| *p != prompt);
+
+        if let (Some(true), Some(check), Some(new_prompt)) = (found, &self.pii, &pii_prompt) {
+            if verdict == Verdict::Allowed {
+                verdict = match check.method {
+                    pii::InputMethod::Block => Verdict::Blocked {
+                        reason: Reason::Pii,
+                        score: None,
+                    },
+                    pii::InputMethod::Replace => Verdict::Redacted(new_prompt.clone()),
+                };
+            }
+        }
+
+        Ok(Report {
+            verdict,
+            scores: Scores {
+                injection,
+                pii: found,
+                pii_prompt,
+                pii_local,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Runs the toxicity and factuality checks on an output concurrently. Toxicity
+    /// blocks the output before factuality is considered.
+    ///
+    /// ## Arguments
+    ///
+    /// * `output` - The output of the model to check.
+    ///
+    /// Returns an error if any of the check requests fail.
+    pub async fn check_output(&self, output: String) -> Result<Report> {
+        let (toxicity, factuality) = future::try_join(
+            self.toxicity_score(&output),
+            self.factuality_scores(&output),
+        )
+        .await?;
+
+        let mut verdict = match (toxicity, self.toxicity) {
+            (Some(score), Some(threshold)) if score >= threshold => Verdict::Blocked {
+                reason: Reason::Toxicity,
+                score: Some(score),
+            },
+            _ => Verdict::Allowed,
+        };
+
+        let best = factuality.iter().flatten().copied().reduce(f64::max);
+
+        if let (Some(best), Some(check)) = (best, &self.factuality) {
+            if best < check.threshold && verdict == Verdict::Allowed {
+                verdict = Verdict::Blocked {
+                    reason: Reason::Factuality,
+                    score: Some(best),
+                };
+            }
+        }
+
+        Ok(Report {
+            verdict,
+            scores: Scores {
+                toxicity,
+                factuality,
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn injection_score(&self, prompt: &str) -> Result<Option<f64>> {
+        if self.injection.is_none() {
+            return Ok(None);
+        }
+
+        let req = injection::Request::new(prompt.to_string(), true);
+        let resp = self.client.injection(&req).await?;
+
+        first(resp.checks.first().map(|c| c.probability), "injection").map(Some)
+    }
+
+    /// Returns the prompt with the PII replaced, `None` if the PII check is not
+    /// configured, and true if the endpoint could not be reached and only the local
+    /// detector replaced the PII. The PII is replaced for both methods, a changed
+    /// prompt shows that PII was found.
+    async fn pii_prompt(&self, prompt: &str) -> Result<(Option<String>, bool)> {
+        let Some(check) = &self.pii else {
+            return Ok((None, false));
+        };
+
+        #[cfg(feature = "local-pii")]
+        if let Some(detector) = &self.local_pii {
+            let filtered = detector.replace(prompt, check.replace_method);
+            let req = pii::Request::new(filtered.clone(), true, check.replace_method);
+
+            return match self.client.pii(&req).await {
+                Ok(resp) => first(resp.checks.into_iter().next().map(|c| c.new_prompt), "pii")
+                    .map(|p| (Some(p), false)),
+                Err(err) if unreachable(&err) => {
+                    warn!(
+                        "pii endpoint unavailable, using the local detector: {}",
+                        err
+                    );
+                    Ok((Some(filtered), true))
+                }
+                Err(err) => Err(err),
+            };
+        }
+
+        let req = pii::Request::new(prompt.to_string(), true, check.replace_method);
+        let resp = self.client.pii(&req).await?;
+
+        first(resp.checks.into_iter().next().map(|c| c.new_prompt), "pii").map(|p| (Some(p), false))
+    }
+
+    async fn toxicity_score(&self, output: &str) -> Result<Option<f64>> {
+        if self.toxicity.is_none() {
+            return Ok(None);
+        }
+
+        let req = toxicity::Request::new(output.to_string());
+        let resp = self.client.toxicity(&req).await?;
+
+        first(resp.checks.first().map(|c| c.score), "toxicity").map(Some)
+    }
+
+    /// Returns the factuality score for each reference, checked concurrently.
+    async fn factuality_scores(&self, output: &str) -> Result<Option<Vec<f64>>> {
+        let Some(check) = &self.factuality else {
+            return Ok(None);
+        };
+
+        let scores = future::try_join_all(check.references.iter().map(|reference| async {
+            let req = factuality::Request::new(reference.clone(), output.to_string());
+            let resp = self.client.check_factuality(&req).await?;
+
+            first(resp.checks.first().map(|c| c.score), "factuality")
+        }))
+        .await?;
+
+        Ok(Some(scores))
+    }
+}
+
+/// Returns true if the error shows that the API could not be reached or failed.
+#[cfg(feature = "local-pii")]
+fn unreachable(err: &PgError) -> bool {
+    match err {
+        PgError::Transport(_) | PgError::Timeout(_) => true,
+        _ => err.status().is_some_and(|s| s.is_server_error()),
+    }
+}
+
+/// Returns the value of the first check, or an error if the response had no checks.
+pub(crate) fn first<T>(value: Option<T>, endpoint: &str) -> Result<T> {
+    value.ok_or_else(|| PgError::Response(format!("no checks in the {} response", endpoint)))
+}
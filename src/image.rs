@@ -1,34 +1,327 @@
-//! Utility module used to download and base64 encode an image.
-use base64;
-use base64::Engine;
+//! Utility module used to load, validate and base64 encode an image.
+//!
+//! Images can be loaded from bytes, a file, any [`AsyncRead`] or downloaded with
+//! [`Client::download_image`](crate::client::Client::download_image). The format is
+//! detected from the magic bytes of the image, only PNG, JPEG, GIF and WebP images are
+//! accepted, and images larger than the maximum size are rejected before they are
+//! encoded.
+//!
+//! With the `preprocess` feature images can also be downscaled and recompressed before
+//! they are sent, see `Preprocess`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{chat, image};
+//!
+//! let img = image::Image::from_path("skyline.jpg", image::DEFAULT_MAX_SIZE).await?;
+//!
+//! let req = chat::Request::<chat::Message>::new("llava-1.5-7b-hf".to_string()).add_message_parts(
+//!     chat::Roles::User,
+//!     vec![
+//!         chat::Content::text("What is in this image?".to_string()),
+//!         chat::Content::image_url(img.to_data_uri()),
+//!     ],
+//! );
+//! ```
+use std::{fmt, path::Path};
+
 use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use futures::StreamExt;
+use reqwest::Response;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{PgError, Result};
+
+#[cfg(feature = "preprocess")]
+mod preprocess;
+
+#[cfg(feature = "preprocess")]
+pub use preprocess::{Output, Preprocess, Preprocessed, DEFAULT_QUALITY};
+
+/// The default maximum size of an image, 20 MiB.
+pub const DEFAULT_MAX_SIZE: usize = 20 * 1024 * 1024;
+
+/// The image formats accepted by the API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+}
+
+impl Format {
+    /// Detects the format from the magic bytes at the start of the image.
+    ///
+    /// ## Arguments
+    ///
+    /// * `bytes` - The image, only the first 12 bytes are used.
+    ///
+    /// Returns `None` if the bytes are not a PNG, JPEG, GIF or WebP image.
+    pub fn detect(bytes: &[u8]) -> Option<Format> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Format::Png)
+        } else if bytes.starts_with(b"\xFF\xD8\xFF") {
+            Some(Format::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Format::Gif)
+        } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            Some(Format::Webp)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the MIME type of the format, e.g. `image/png`.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Format::Png => "image/png",
+            Format::Jpeg => "image/jpeg",
+            Format::Gif => "image/gif",
+            Format::Webp => "image/webp",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.mime_type())
+    }
+}
+
+/// An image with a known format that is no larger than the maximum size it was
+/// loaded with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    format: Format,
+    bytes: Vec<u8>,
+}
+
+impl Image {
+    /// Creates an image from bytes.
+    ///
+    /// ## Arguments
+    ///
+    /// * `bytes` - The image.
+    /// * `max_size` - The maximum size of the image in bytes.
+    ///
+    /// Returns an error if the image is too large or the format is not supported.
+    pub fn from_bytes(bytes: &[u8], max_size: usize) -> Result<Self> {
+        check_size(bytes.len() as u64, max_size)?;
+
+        Self::from_vec(bytes.to_vec())
+    }
+
+    /// Reads an image from a file. The size of the file is checked before it is read.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The path of the image file.
+    /// * `max_size` - The maximum size of the image in bytes.
+    pub async fn from_path<P: AsRef<Path>>(path: P, max_size: usize) -> Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        check_size(file.metadata().await?.len(), max_size)?;
+
+        Self::from_reader(file, max_size).await
+    }
+
+    /// Reads an image from a reader until it ends. Reading stops as soon as the image
+    /// is larger than the maximum size.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader` - The reader of the image.
+    /// * `max_size` - The maximum size of the image in bytes.
+    pub async fn from_reader<R: AsyncRead + Unpin>(reader: R, max_size: usize) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut bytes)
+            .await?;
+
+        check_size(bytes.len() as u64, max_size)?;
+
+        Self::from_vec(bytes)
+    }
+
+    /// Reads an image from the body of a response, used by
+    /// [`Client::download_image`](crate::client::Client::download_image).
+    pub(crate) async fn from_response(resp: Response, max_size: usize) -> Result<Self> {
+        if let Some(len) = resp.content_length() {
+            check_size(len, max_size)?;
+        }
+
+        let mut body = resp.bytes_stream();
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = body.next().await {
+            bytes.extend_from_slice(&chunk?);
+            check_size(bytes.len() as u64, max_size)?;
+        }
+
+        Self::from_vec(bytes)
+    }
+
+    fn from_vec(bytes: Vec<u8>) -> Result<Self> {
+        let format = Format::detect(&bytes).ok_or_else(|| {
+            PgError::Image("unsupported format, expected png, jpeg, gif or webp".to_string())
+        })?;
+
+        Ok(Self { format, bytes })
+    }
+
+    /// Returns the detected format of the image.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns the bytes of the image.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the size of the image in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the image base64 encoded, as expected by [`crate::embedding::Request`].
+    pub fn to_base64(&self) -> String {
+        BASE64_STANDARD.encode(&self.bytes)
+    }
+
+    /// Returns the image as a data URI, e.g. `data:image/png;base64,...`, ready to be
+    /// used with [`crate::chat::Content::image_url`].
+    pub fn to_data_uri(&self) -> String {
+        let mut uri = format!("data:{};base64,", self.format.mime_type());
+        BASE64_STANDARD.encode_string(&self.bytes, &mut uri);
+        uri
+    }
+}
 
-/// Downloads and base64 encodes the image specified by the URL
+fn check_size(size: u64, max_size: usize) -> Result<()> {
+    if size > max_size as u64 {
+        return Err(PgError::Image(format!(
+            "image is larger than the maximum size of {} bytes",
+            max_size
+        )));
+    }
+
+    Ok(())
+}
+
+/// Downloads and base64 encodes the image specified by the URL.
+///
+/// The image is downloaded with a default HTTP client and must be no larger than
+/// [`DEFAULT_MAX_SIZE`]. Use [`Client::download_image`](crate::client::Client::download_image)
+/// to download it with the settings of the client.
 ///
 /// ## Arguments
 ///
 /// * `url` - The url of the image to download.
 pub async fn encode(url: String) -> crate::Result<String> {
-    let img = reqwest::get(url).await?.bytes().await?;
+    let resp = reqwest::get(url).await?.error_for_status()?;
 
-    let mut img_str = String::new();
-    BASE64_STANDARD.encode_string(img, &mut img_str);
-    Ok(img_str)
+    let img = Image::from_response(resp, DEFAULT_MAX_SIZE).await?;
+
+    Ok(img.to_base64())
 }
 
 #[cfg(test)]
 mod tests {
+    use httpmock::prelude::*;
+
+    use super::*;
     use crate::image;
 
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn format_detection() {
+        let cases: [(&[u8], Option<Format>); 8] = [
+            (PNG, Some(Format::Png)),
+            (b"\xFF\xD8\xFF\xE0\0\x10JFIF", Some(Format::Jpeg)),
+            (b"GIF87a\x01\0\x01\0", Some(Format::Gif)),
+            (b"GIF89a\x01\0\x01\0", Some(Format::Gif)),
+            (b"RIFF\x24\0\0\0WEBPVP8 ", Some(Format::Webp)),
+            (b"RIFF\x24\0\0\0WAVEfmt ", None),
+            (b"\x89PN", None),
+            (b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>", None),
+        ];
+
+        for (bytes, expected) in cases {
+            assert_eq!(Format::detect(bytes), expected, "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn data_uri() {
+        let img = Image::from_bytes(b"GIF89a", DEFAULT_MAX_SIZE).expect("gif");
+
+        assert_eq!(img.format(), Format::Gif);
+        assert_eq!(img.size(), 6);
+        assert_eq!(img.to_base64(), "R0lGODlh");
+        assert_eq!(img.to_data_uri(), "data:image/gif;base64,R0lGODlh");
+    }
+
+    #[test]
+    fn invalid_images() {
+        let err = Image::from_bytes(b"plain text", DEFAULT_MAX_SIZE).expect_err("format");
+        assert!(matches!(err, PgError::Image(_)), "{:?}", err);
+
+        let err = Image::from_bytes(PNG, PNG.len() - 1).expect_err("size");
+        assert!(err.to_string().contains("maximum size"), "{}", err);
+
+        assert!(Image::from_bytes(PNG, PNG.len()).is_ok());
+    }
+
+    #[test]
+    fn image_reader() {
+        tokio_test::block_on(async {
+            let img = Image::from_reader(PNG, PNG.len()).await.expect("png");
+            assert_eq!(img.bytes(), PNG);
+
+            let err = Image::from_reader(PNG, 8).await.expect_err("size");
+            assert!(matches!(err, PgError::Image(_)), "{:?}", err);
+        });
+    }
+
+    #[test]
+    fn image_path() {
+        tokio_test::block_on(async {
+            let path = std::env::temp_dir().join(format!("pg-image-{}.png", std::process::id()));
+            std::fs::write(&path, PNG).expect("write image");
+
+            let img = Image::from_path(&path, DEFAULT_MAX_SIZE).await;
+            let too_large = Image::from_path(&path, 4).await;
+            std::fs::remove_file(&path).expect("remove image");
+
+            assert_eq!(img.expect("png").format(), Format::Png);
+            assert!(matches!(too_large, Err(PgError::Image(_))));
+
+            let missing = Image::from_path(path, DEFAULT_MAX_SIZE).await;
+            assert!(matches!(missing, Err(PgError::Io(_))));
+        });
+    }
+
     #[test]
     fn image_encode() {
         tokio_test::block_on(async {
-            let url = "https://farm4.staticflickr.com/3300/3497460990_11dfb95dd1_z.jpg](https://farm4.staticflickr.com/3300/3497460990_11dfb95dd1_z.jpg";
+            let server = MockServer::start();
+
+            let mock = server.mock(|when, then| {
+                when.method(GET).path("/image.png");
+                then.status(200)
+                    .header("content-type", "image/png")
+                    .body(PNG);
+            });
 
-            let encoded_str = image::encode(url.to_string()).await.unwrap();
+            let encoded_str = image::encode(server.url("/image.png")).await.unwrap();
 
-            assert!(!encoded_str.is_empty());
-            println!("Image-> \n{:?}", encoded_str.clone());
+            mock.assert();
+            assert_eq!(encoded_str, BASE64_STANDARD.encode(PNG));
         });
     }
 }
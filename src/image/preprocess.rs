@@ -0,0 +1,292 @@
+//! Downscales and recompresses images before they are sent, enabled with the
+//! `preprocess` feature.
+use std::io::Cursor;
+
+use image_rs::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageDecoder};
+
+use super::{Format, Image};
+use crate::{PgError, Result};
+
+/// The quality used when an image is encoded as JPEG or WebP without a quality set.
+pub const DEFAULT_QUALITY: u8 = 85;
+
+/// The format of a preprocessed image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Output {
+    /// Keeps the format of the image. GIF images are converted to PNG since only the
+    /// first frame is kept, JPEG and WebP images use [`DEFAULT_QUALITY`].
+    #[default]
+    Original,
+    /// Encodes the image as JPEG with a quality from 1 to 100. Transparency is removed.
+    Jpeg(u8),
+    /// Encodes the image as lossy WebP with a quality from 1 to 100.
+    Webp(u8),
+}
+
+/// Options to preprocess an image with [`Image::preprocess`].
+///
+/// The image is always decoded and encoded again, which removes the EXIF data and any
+/// other metadata. The EXIF orientation is applied to the pixels first, so the image
+/// is displayed the same way without it.
+///
+/// # Example
+///
+/// ```ignore
+/// use prediction_guard::image;
+///
+/// let img = image::Image::from_path("photo.jpg", image::DEFAULT_MAX_SIZE).await?;
+///
+/// let small = img.preprocess(&image::Preprocess::new().max_dimension(1024).jpeg(80))?;
+/// println!("{} bytes -> {} bytes", small.original_size, small.size());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Preprocess {
+    max_dimension: Option<u32>,
+    output: Output,
+}
+
+impl Preprocess {
+    /// Creates the options to strip the metadata and keep the size and format.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum width and height. Larger images are downscaled keeping the
+    /// aspect ratio, smaller images are not upscaled.
+    ///
+    /// ## Arguments
+    ///
+    /// * `max` - The maximum width and height in pixels. A value of 0 is treated as 1.
+    pub fn max_dimension(mut self, max: u32) -> Self {
+        self.max_dimension = Some(max.max(1));
+        self
+    }
+
+    /// Encodes the image as JPEG.
+    ///
+    /// ## Arguments
+    ///
+    /// * `quality` - The quality from 1 to 100.
+    pub fn jpeg(mut self, quality: u8) -> Self {
+        self.output = Output::Jpeg(quality.clamp(1, 100));
+        self
+    }
+
+    /// Encodes the image as lossy WebP.
+    ///
+    /// ## Arguments
+    ///
+    /// * `quality` - The quality from 1 to 100.
+    pub fn webp(mut self, quality: u8) -> Self {
+        self.output = Output::Webp(quality.clamp(1, 100));
+        self
+    }
+}
+
+/// An image returned from [`Image::preprocess`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preprocessed {
+    /// The preprocessed image.
+    pub image: Image,
+    /// The width of the preprocessed image in pixels.
+    pub width: u32,
+    /// The height of the preprocessed image in pixels.
+    pub height: u32,
+    /// The size of the image in bytes before it was preprocessed.
+    pub original_size: usize,
+}
+
+impl Preprocessed {
+    /// Returns the size of the preprocessed image in bytes.
+    pub fn size(&self) -> usize {
+        self.image.size()
+    }
+}
+
+impl Image {
+    /// Resizes and encodes the image again with the options. This is CPU bound, use
+    /// `tokio::task::spawn_blocking` to run it for large images in an async context.
+    ///
+    /// ## Arguments
+    ///
+    /// * `options` - The options to preprocess the image with.
+    ///
+    /// Returns an error if the image can not be decoded or encoded.
+    pub fn preprocess(&self, options: &Preprocess) -> Result<Preprocessed> {
+        let mut img = decode(&self.bytes)?;
+
+        if let Some(max) = options.max_dimension {
+            if img.width() > max || img.height() > max {
+                img = img.resize(max, max, FilterType::Lanczos3);
+            }
+        }
+
+        let output = match (options.output, self.format) {
+            (Output::Original, Format::Jpeg) => Output::Jpeg(DEFAULT_QUALITY),
+            (Output::Original, Format::Webp) => Output::Webp(DEFAULT_QUALITY),
+            (output, _) => output,
+        };
+
+        let bytes = encode(&img, output)?;
+
+        Ok(Preprocessed {
+            image: Image::from_vec(bytes)?,
+            width: img.width(),
+            height: img.height(),
+            original_size: self.size(),
+        })
+    }
+}
+
+fn decode(bytes: &[u8]) -> Result<DynamicImage> {
+    let mut decoder = image_rs::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(image_error)?;
+
+    let orientation = decoder.orientation().map_err(image_error)?;
+
+    let mut img = DynamicImage::from_decoder(decoder).map_err(image_error)?;
+    img.apply_orientation(orientation);
+
+    Ok(img)
+}
+
+fn encode(img: &DynamicImage, output: Output) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    match output {
+        Output::Original => {
+            img.write_to(&mut Cursor::new(&mut bytes), image_rs::ImageFormat::Png)
+                .map_err(image_error)?;
+        }
+        Output::Jpeg(quality) => {
+            JpegEncoder::new_with_quality(&mut bytes, quality)
+                .encode_image(&img.to_rgb8())
+                .map_err(image_error)?;
+        }
+        Output::Webp(quality) => {
+            let rgba = img.to_rgba8();
+            let webp = webp::Encoder::from_rgba(rgba.as_raw(), rgba.width(), rgba.height())
+                .encode(f32::from(quality));
+            bytes.extend_from_slice(&webp);
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn image_error(e: image_rs::ImageError) -> PgError {
+    PgError::Image(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use image_rs::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::image::DEFAULT_MAX_SIZE;
+
+    fn png(width: u32, height: u32) -> Image {
+        let img = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, 128, 255])
+        });
+
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgba8(img)
+            .write_to(&mut Cursor::new(&mut bytes), image_rs::ImageFormat::Png)
+            .expect("png");
+
+        Image::from_bytes(&bytes, DEFAULT_MAX_SIZE).expect("image")
+    }
+
+    /// Returns a JPEG with an EXIF segment that rotates the image 90 degrees.
+    fn rotated_jpeg(width: u32, height: u32) -> Image {
+        let img = png(width, height)
+            .preprocess(&Preprocess::new().jpeg(90))
+            .expect("jpeg")
+            .image;
+
+        let tiff: &[u8] = b"II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0";
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend_from_slice(tiff);
+
+        let mut bytes = img.bytes()[..2].to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xE1]);
+        bytes.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(&exif);
+        bytes.extend_from_slice(&img.bytes()[2..]);
+
+        Image::from_bytes(&bytes, DEFAULT_MAX_SIZE).expect("image")
+    }
+
+    #[test]
+    fn resize() {
+        let img = png(400, 200);
+
+        let result = img
+            .preprocess(&Preprocess::new().max_dimension(100))
+            .expect("preprocess");
+
+        assert_eq!((result.width, result.height), (100, 50));
+        assert_eq!(result.image.format(), Format::Png);
+        assert_eq!(result.original_size, img.size());
+        assert!(result.size() < img.size());
+
+        let result = img
+            .preprocess(&Preprocess::new().max_dimension(1000))
+            .expect("preprocess");
+
+        assert_eq!((result.width, result.height), (400, 200));
+    }
+
+    #[test]
+    fn output_formats() {
+        let img = png(64, 32);
+
+        let jpeg = img.preprocess(&Preprocess::new().jpeg(80)).expect("jpeg");
+        assert_eq!(jpeg.image.format(), Format::Jpeg);
+        assert!(jpeg
+            .image
+            .to_data_uri()
+            .starts_with("data:image/jpeg;base64,"));
+
+        let webp = img.preprocess(&Preprocess::new().webp(80)).expect("webp");
+        assert_eq!(webp.image.format(), Format::Webp);
+        assert_eq!((webp.width, webp.height), (64, 32));
+
+        let low = img.preprocess(&Preprocess::new().jpeg(10)).expect("jpeg");
+        assert!(low.size() < jpeg.size());
+
+        let gif = Image::from_bytes(
+            b"GIF89a\x01\0\x01\0\x80\0\0\0\0\0\xff\xff\xff!\xf9\x04\x01\0\0\0\0,\0\0\0\0\x01\0\x01\0\0\x02\x02D\x01\0;",
+            DEFAULT_MAX_SIZE,
+        )
+        .expect("gif");
+        let result = gif.preprocess(&Preprocess::new()).expect("gif to png");
+        assert_eq!(result.image.format(), Format::Png);
+    }
+
+    #[test]
+    fn strip_exif() {
+        let img = rotated_jpeg(40, 20);
+        assert!(img.bytes().windows(4).any(|w| w == b"Exif"));
+
+        let result = img.preprocess(&Preprocess::new()).expect("preprocess");
+
+        assert_eq!(result.image.format(), Format::Jpeg);
+        assert_eq!((result.width, result.height), (20, 40));
+        assert!(!result.image.bytes().windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn invalid_image() {
+        let img =
+            Image::from_bytes(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", DEFAULT_MAX_SIZE).expect("png");
+
+        let err = img
+            .preprocess(&Preprocess::new())
+            .expect_err("truncated png");
+        assert!(matches!(err, PgError::Image(_)), "{:?}", err);
+    }
+}
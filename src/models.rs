@@ -1,14 +1,78 @@
 //! Data types that are used for the factuality endpoints.
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
 
 /// Path to the factuality endpoint.
 pub const PATH: &str = "/models";
 
-/// Request type for the factuality endpoint.
+/// A capability of a model, used to list the models that support it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Capability {
+    ChatCompletion,
+    ChatWithImage,
+    Completion,
+    Embedding,
+    EmbeddingWithImage,
+    Tokenize,
+    /// A capability not known to this client, e.g. `rerank`.
+    Other(String),
+}
+
+impl Capability {
+    /// Returns the capability as used in the path of the models endpoint.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Capability::ChatCompletion => "chat-completion",
+            Capability::ChatWithImage => "chat-with-image",
+            Capability::Completion => "completion",
+            Capability::Embedding => "embedding",
+            Capability::EmbeddingWithImage => "embedding-with-image",
+            Capability::Tokenize => "tokenize",
+            Capability::Other(s) => s.as_str(),
+        }
+    }
+}
+
+impl From<String> for Capability {
+    fn from(s: String) -> Self {
+        // Accept the names of the capability fields too, e.g. chat_completion.
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "chat-completion" => Capability::ChatCompletion,
+            "chat-with-image" => Capability::ChatWithImage,
+            "completion" => Capability::Completion,
+            "embedding" => Capability::Embedding,
+            "embedding-with-image" => Capability::EmbeddingWithImage,
+            "tokenize" => Capability::Tokenize,
+            _ => Capability::Other(s),
+        }
+    }
+}
+
+impl From<&str> for Capability {
+    fn from(s: &str) -> Self {
+        Capability::from(s.to_string())
+    }
+}
+
+impl From<Capability> for String {
+    fn from(c: Capability) -> Self {
+        c.as_str().to_string()
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Request type for the models endpoint.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Request {
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub capability: Option<String>,
+    pub capability: Option<Capability>,
 }
 
 impl Request {
@@ -16,13 +80,13 @@ impl Request {
     ///
     /// ## Arguments
     ///
-    /// * `capability` - The capability to sort models by.
-    pub fn new(capability: Option<String>) -> Request {
+    /// * `capability` - The capability to filter the models by, all models are returned if `None`.
+    pub fn new(capability: Option<Capability>) -> Request {
         Self { capability }
     }
 }
 /// Represents the capabilities for a single model.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct ModelCapabilities {
     pub chat_completion: bool,
@@ -31,10 +95,31 @@ pub struct ModelCapabilities {
     pub embedding: bool,
     pub embedding_with_image: bool,
     pub tokenize: bool,
+    pub rerank: bool,
+}
+
+impl ModelCapabilities {
+    /// Returns true if the model has the capability. For [`Capability::Other`] only
+    /// `rerank` is known.
+    ///
+    /// ## Arguments
+    ///
+    /// * `capability` - The capability to check.
+    pub fn supports(&self, capability: &Capability) -> bool {
+        match capability {
+            Capability::ChatCompletion => self.chat_completion,
+            Capability::ChatWithImage => self.chat_with_image,
+            Capability::Completion => self.completion,
+            Capability::Embedding => self.embedding,
+            Capability::EmbeddingWithImage => self.embedding_with_image,
+            Capability::Tokenize => self.tokenize,
+            Capability::Other(s) => s == "rerank" && self.rerank,
+        }
+    }
 }
 
 /// Represents a single model response.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct ModelData {
     pub id: String,
@@ -48,9 +133,157 @@ pub struct ModelData {
 }
 
 /// Response type for the models endpoint.
-#[derive(Debug, Default, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Response {
     pub object: String,
     pub data: Vec<ModelData>,
 }
+
+impl Response {
+    /// Returns the models that have the capability.
+    ///
+    /// ## Arguments
+    ///
+    /// * `capability` - The capability of the models.
+    pub fn with_capability(&self, capability: &Capability) -> Vec<&ModelData> {
+        self.data
+            .iter()
+            .filter(|m| m.capabilities.supports(capability))
+            .collect()
+    }
+
+    /// Returns the models with a context length of at least the minimum.
+    ///
+    /// ## Arguments
+    ///
+    /// * `min` - The minimum context length in tokens.
+    pub fn with_min_context_length(&self, min: i64) -> Vec<&ModelData> {
+        self.data
+            .iter()
+            .filter(|m| m.max_context_length >= min)
+            .collect()
+    }
+
+    /// Picks the model with the capability and the smallest context length of at least
+    /// the minimum, usually the smallest model that fits the request.
+    ///
+    /// ## Arguments
+    ///
+    /// * `capability` - The capability of the model.
+    /// * `min_context_length` - The minimum context length in tokens.
+    pub fn pick(&self, capability: &Capability, min_context_length: i64) -> Option<&ModelData> {
+        self.data
+            .iter()
+            .filter(|m| {
+                m.capabilities.supports(capability) && m.max_context_length >= min_context_length
+            })
+            .min_by_key(|m| m.max_context_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capability_names() {
+        let cases = [
+            ("chat-completion", Capability::ChatCompletion),
+            ("chat-with-image", Capability::ChatWithImage),
+            ("completion", Capability::Completion),
+            ("embedding", Capability::Embedding),
+            ("embedding-with-image", Capability::EmbeddingWithImage),
+            ("tokenize", Capability::Tokenize),
+            ("rerank", Capability::Other("rerank".to_string())),
+        ];
+
+        for (s, capability) in cases {
+            let json = format!(r#""{}""#, s);
+            assert_eq!(
+                serde_json::from_str::<Capability>(&json).unwrap(),
+                capability
+            );
+            assert_eq!(serde_json::to_string(&capability).unwrap(), json);
+            assert_eq!(capability.to_string(), s);
+        }
+
+        assert_eq!(
+            Capability::from("chat_with_image"),
+            Capability::ChatWithImage
+        );
+        assert_eq!(Capability::from("Embedding"), Capability::Embedding);
+        assert_eq!(
+            Capability::from("chat-completions"),
+            Capability::Other("chat-completions".to_string())
+        );
+
+        let req = Request::new(Some(Capability::ChatWithImage));
+        assert_eq!(
+            serde_json::to_string(&req).unwrap(),
+            r#"{"capability":"chat-with-image"}"#
+        );
+    }
+
+    #[test]
+    fn response_filters() {
+        let model = |id: &str, context: i64, caps: ModelCapabilities| ModelData {
+            id: id.to_string(),
+            max_context_length: context,
+            capabilities: caps,
+            ..Default::default()
+        };
+
+        let chat = ModelCapabilities {
+            chat_completion: true,
+            completion: true,
+            ..Default::default()
+        };
+
+        let resp = Response {
+            object: "list".to_string(),
+            data: vec![
+                model("large", 32768, chat.clone()),
+                model("small", 8192, chat.clone()),
+                model(
+                    "vision",
+                    8192,
+                    ModelCapabilities {
+                        chat_with_image: true,
+                        ..chat
+                    },
+                ),
+                model(
+                    "reranker",
+                    512,
+                    ModelCapabilities {
+                        rerank: true,
+                        ..Default::default()
+                    },
+                ),
+            ],
+        };
+
+        let ids = |models: Vec<&ModelData>| models.iter().map(|m| m.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(resp.with_capability(&Capability::ChatCompletion)),
+            ["large", "small", "vision"]
+        );
+        assert_eq!(
+            ids(resp.with_capability(&Capability::ChatWithImage)),
+            ["vision"]
+        );
+        assert_eq!(ids(resp.with_capability(&"rerank".into())), ["reranker"]);
+        assert_eq!(
+            ids(resp.with_min_context_length(8192)),
+            ["large", "small", "vision"]
+        );
+
+        let pick = |capability: Capability, min| resp.pick(&capability, min).map(|m| m.id.as_str());
+        assert_eq!(pick(Capability::ChatCompletion, 4096), Some("small"));
+        assert_eq!(pick(Capability::ChatCompletion, 16384), Some("large"));
+        assert_eq!(pick(Capability::ChatWithImage, 16384), None);
+        assert_eq!(pick(Capability::Embedding, 0), None);
+    }
+}
@@ -0,0 +1,420 @@
+//! Moderation of streamed chat completions.
+//!
+//! The output checks of a chat request are not supported when streaming, so a streamed
+//! answer is not checked for toxicity. A [`Moderator`] buffers the deltas of each choice
+//! until a sentence is complete, checks the sentence with the toxicity endpoint and
+//! optionally the PII endpoint, and only then sends it on. A sentence that fails a
+//! check is either redacted, or the stream is ended with a [`PgError::Moderation`].
+//!
+//! # Example
+//!
+//! ```ignore
+//! use futures::TryStreamExt;
+//! use prediction_guard::{chat, client, moderation, pii};
+//!
+//! let clt = client::Client::new()?;
+//!
+//! let moderator = moderation::Moderator::new(clt)
+//!     .toxicity(0.7)
+//!     .pii(pii::ReplaceMethod::Mask)
+//!     .action(moderation::Action::Terminate);
+//!
+//! let mut stream = moderator.generate_chat_completion_stream(&req).await?;
+//!
+//! while let Some(evt) = stream.try_next().await? {
+//!     if let Some(choice) = evt.choices.first() {
+//!         print!("{}", choice.delta.content);
+//!     }
+//! }
+//! ```
+use std::collections::HashMap;
+
+use futures::{
+    future,
+    stream::{self, BoxStream, StreamExt},
+};
+use serde::Serialize;
+
+use crate::{
+    chat,
+    client::Client,
+    guard::{first, Reason},
+    pii, toxicity, PgError, Result,
+};
+
+/// The number of bytes buffered for a choice before the text is checked, even if the
+/// sentence is not complete.
+pub const MAX_SEGMENT: usize = 400;
+
+/// The text sent instead of a toxic sentence by [`Action::Redact`].
+pub const DEFAULT_REDACTION: &str = "[removed]";
+
+/// What to do with a sentence that fails a check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Action {
+    /// Sends the redaction instead of a toxic sentence, and the sentence with the PII
+    /// replaced instead of a sentence with PII. The stream continues.
+    #[default]
+    Redact,
+    /// Ends the stream with a [`PgError::Moderation`]. The failed sentence is not sent.
+    Terminate,
+}
+
+/// Checks the text of streamed chat completions sentence by sentence.
+#[derive(Debug, Clone)]
+pub struct Moderator {
+    client: Client,
+    toxicity: Option<f64>,
+    pii: Option<pii::ReplaceMethod>,
+    action: Action,
+    redaction: String,
+}
+
+impl Moderator {
+    /// Creates a new moderator without checks.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the check endpoints.
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            toxicity: None,
+            pii: None,
+            action: Action::default(),
+            redaction: DEFAULT_REDACTION.to_string(),
+        }
+    }
+
+    /// Checks each sentence for toxicity.
+    ///
+    /// ## Arguments
+    ///
+    /// * `threshold` - The score from 0.0 to 1.0 at which a sentence fails the check.
+    pub fn toxicity(mut self, threshold: f64) -> Self {
+        self.toxicity = Some(threshold);
+        self
+    }
+
+    /// Checks each sentence for PII.
+    ///
+    /// ## Arguments
+    ///
+    /// * `replace_method` - The method used to replace the PII when it is redacted.
+    pub fn pii(mut self, replace_method: pii::ReplaceMethod) -> Self {
+        self.pii = Some(replace_method);
+        self
+    }
+
+    /// Sets what to do with a sentence that fails a check.
+    ///
+    /// ## Arguments
+    ///
+    /// * `action` - Redacts the sentence or ends the stream.
+    pub fn action(mut self, action: Action) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Sets the text sent instead of a toxic sentence.
+    ///
+    /// ## Arguments
+    ///
+    /// * `redaction` - The replacement text, [`DEFAULT_REDACTION`] by default.
+    pub fn redaction(mut self, redaction: String) -> Self {
+        self.redaction = redaction;
+        self
+    }
+
+    /// Calls the chat completion endpoint and returns the stream of events with the
+    /// text moderated, see [`Moderator::moderate`].
+    ///
+    /// ## Arguments
+    ///
+    /// * `req` - An instance of [`chat::Request`].
+    ///
+    /// Returns an error if the chat endpoint fails.
+    pub async fn generate_chat_completion_stream<T: Serialize>(
+        &self,
+        req: &chat::Request<T>,
+    ) -> Result<BoxStream<'static, Result<chat::ResponseEvents>>> {
+        let stream = self.client.generate_chat_completion_stream(req).await?;
+
+        Ok(self.moderate(stream))
+    }
+
+    /// Moderates a stream of chat completion events.
+    ///
+    /// The content of each choice is held back until a sentence ends, or until
+    /// [`MAX_SEGMENT`] bytes are buffered, and is sent with the event that completes
+    /// it. Events are still sent while the text is held back, with empty content. The
+    /// rest of the text is checked and sent with the event that has the finish reason,
+    /// or with an extra event if the stream ends without one.
+    ///
+    /// An error from the stream or from a check request ends the stream.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stream` - The stream returned by [`Client::generate_chat_completion_stream`].
+    pub fn moderate(
+        &self,
+        stream: BoxStream<'static, Result<chat::ResponseEvents>>,
+    ) -> BoxStream<'static, Result<chat::ResponseEvents>> {
+        let state = State {
+            moderator: self.clone(),
+            stream,
+            buffers: HashMap::new(),
+            last: None,
+            done: false,
+        };
+
+        stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+
+            let event = match state.stream.next().await {
+                Some(Ok(event)) => state.moderate_event(event).await,
+                Some(Err(err)) => Err(err),
+                None => {
+                    state.done = true;
+                    return state.flush().await.map(|event| (event, state));
+                }
+            };
+
+            state.done = event.is_err();
+            Some((event, state))
+        })
+        .boxed()
+    }
+
+    /// Checks a segment of text and returns the text to send.
+    async fn check(&self, segment: String, index: i64) -> Result<String> {
+        if segment.trim().is_empty() {
+            return Ok(segment);
+        }
+
+        let (score, replaced) =
+            future::try_join(self.toxicity_score(&segment), self.pii_text(&segment)).await?;
+
+        if let (Some(score), Some(threshold)) = (score, self.toxicity) {
+            if score >= threshold {
+                return match self.action {
+                    Action::Terminate => Err(PgError::Moderation {
+                        reason: Reason::Toxicity,
+                        score: Some(score),
+                        index,
+                    }),
+                    Action::Redact => Ok(keep_whitespace(&segment, &self.redaction)),
+                };
+            }
+        }
+
+        match replaced {
+            Some(replaced) if replaced.trim() != segment.trim() => match self.action {
+                Action::Terminate => Err(PgError::Moderation {
+                    reason: Reason::Pii,
+                    score: None,
+                    index,
+                }),
+                Action::Redact => Ok(keep_whitespace(&segment, replaced.trim())),
+            },
+            _ => Ok(segment),
+        }
+    }
+
+    async fn toxicity_score(&self, text: &str) -> Result<Option<f64>> {
+        if self.toxicity.is_none() {
+            return Ok(None);
+        }
+
+        let req = toxicity::Request::new(text.trim().to_string());
+        let resp = self.client.toxicity(&req).await?;
+
+        first(resp.checks.first().map(|c| c.score), "toxicity").map(Some)
+    }
+
+    /// Returns the text with the PII replaced.
+    async fn pii_text(&self, text: &str) -> Result<Option<String>> {
+        let Some(replace_method) = self.pii else {
+            return Ok(None);
+        };
+
+        let req = pii::Request::new(text.trim().to_string(), true, replace_method);
+        let resp = self.client.pii(&req).await?;
+
+        first(resp.checks.into_iter().next().map(|c| c.new_prompt), "pii").map(Some)
+    }
+}
+
+struct State {
+    moderator: Moderator,
+    stream: BoxStream<'static, Result<chat::ResponseEvents>>,
+    /// The text held back for each choice index.
+    buffers: HashMap<i64, String>,
+    /// The last event, used for the event that sends the rest of the text.
+    last: Option<chat::ResponseEvents>,
+    done: bool,
+}
+
+impl State {
+    async fn moderate_event(
+        &mut self,
+        mut event: chat::ResponseEvents,
+    ) -> Result<chat::ResponseEvents> {
+        for choice in &mut event.choices {
+            let buffer = self.buffers.entry(choice.index).or_default();
+            buffer.push_str(&choice.delta.content);
+
+            let segment = take_segment(buffer, choice.finish_reason.is_some());
+
+            if !segment.is_empty() {
+                choice.delta.content = self.moderator.check(segment, choice.index).await?;
+            } else {
+                choice.delta.content = segment;
+            }
+        }
+
+        self.last = Some(event.clone());
+        Ok(event)
+    }
+
+    /// Returns an event with the text that is still held back, if there is any.
+    async fn flush(&mut self) -> Option<Result<chat::ResponseEvents>> {
+        let mut pending: Vec<(i64, String)> = self
+            .buffers
+            .drain()
+            .filter(|(_, text)| !text.is_empty())
+            .collect();
+
+        if pending.is_empty() {
+            return None;
+        }
+
+        pending.sort_by_key(|(index, _)| *index);
+
+        let mut event = self.last.take().unwrap_or_default();
+        event.choices.clear();
+
+        for (index, text) in pending {
+            let content = match self.moderator.check(text, index).await {
+                Ok(content) => content,
+                Err(err) => return Some(Err(err)),
+            };
+
+            event.choices.push(chat::ChoiceEvents {
+                index,
+                delta: chat::EventsDelta {
+                    content,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        }
+
+        Some(Ok(event))
+    }
+}
+
+/// Removes the complete sentences from the start of the buffer and returns them, or
+/// the whole buffer with `flush`.
+fn take_segment(buffer: &mut String, flush: bool) -> String {
+    let end = if flush {
+        Some(buffer.len())
+    } else {
+        sentence_end(buffer).or_else(|| {
+            (buffer.len() >= MAX_SEGMENT).then(|| {
+                buffer
+                    .rfind(char::is_whitespace)
+                    .filter(|i| *i > 0)
+                    .unwrap_or(buffer.len())
+            })
+        })
+    };
+
+    match end {
+        Some(end) => {
+            let rest = buffer.split_off(end);
+            std::mem::replace(buffer, rest)
+        }
+        None => String::new(),
+    }
+}
+
+/// Returns the end of the last complete sentence, after the whitespace that follows
+/// it. A sentence ends with `.`, `!` or `?` followed by whitespace, or with a newline.
+fn sentence_end(text: &str) -> Option<usize> {
+    let mut end = None;
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match (c, chars.peek()) {
+            ('\n', _) => end = Some(i + 1),
+            ('.' | '!' | '?', Some(&(j, next))) if next.is_whitespace() => {
+                end = Some(j + next.len_utf8());
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+
+    end
+}
+
+/// Returns the replacement with the leading and trailing whitespace of the original.
+fn keep_whitespace(original: &str, replacement: &str) -> String {
+    let start = original.len() - original.trim_start().len();
+    let end = original.trim_end().len();
+
+    format!(
+        "{}{}{}",
+        &original[..start],
+        replacement,
+        &original[end.max(start)..]
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences() {
+        assert_eq!(sentence_end("Hello there"), None);
+        assert_eq!(sentence_end("Hello there."), None);
+        assert_eq!(sentence_end("Hello. There"), Some(7));
+        assert_eq!(sentence_end("It costs 3.50 dollars"), None);
+        assert_eq!(sentence_end("Why? Because! Yes"), Some(14));
+        assert_eq!(sentence_end("line one\nline"), Some(9));
+        assert_eq!(sentence_end("Done.\n\nNext"), Some(7));
+    }
+
+    #[test]
+    fn segments() {
+        let mut buffer = "First one. Second".to_string();
+        assert_eq!(take_segment(&mut buffer, false), "First one. ");
+        assert_eq!(buffer, "Second");
+
+        assert_eq!(take_segment(&mut buffer, false), "");
+        assert_eq!(take_segment(&mut buffer, true), "Second");
+        assert!(buffer.is_empty());
+
+        let mut buffer = "word ".repeat(MAX_SEGMENT / 5 + 1);
+        let segment = take_segment(&mut buffer, false);
+        assert!(segment.len() >= MAX_SEGMENT);
+        assert_eq!(buffer, " ");
+
+        let mut buffer = "x".repeat(MAX_SEGMENT);
+        assert_eq!(take_segment(&mut buffer, false).len(), MAX_SEGMENT);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            keep_whitespace("  bad words. ", "[removed]"),
+            "  [removed] "
+        );
+        assert_eq!(keep_whitespace("bad", "[removed]"), "[removed]");
+    }
+}
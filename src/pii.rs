@@ -1,11 +1,17 @@
 //! Data types for the PII detection endpoint.
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "local-pii")]
+mod local;
+
+#[cfg(feature = "local-pii")]
+pub use local::{Category, Entity, LocalDetector};
+
 /// Path to the PII endpoint.
 pub const PATH: &str = "/PII";
 
 /// Denotes the method to check for PII on inputs for completion and chat completions.
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
 pub enum InputMethod {
     #[serde(rename = "replace")]
     Replace,
@@ -15,7 +21,7 @@ pub enum InputMethod {
 }
 
 /// Denotes the different ways to replace any PII information that is found.
-#[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
 pub enum ReplaceMethod {
     #[serde(rename = "random")]
     #[default]
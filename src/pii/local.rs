@@ -0,0 +1,931 @@
+//! Offline PII detection with regular expressions and checksums, enabled with the
+//! `local-pii` feature.
+use std::{
+    fmt,
+    net::Ipv6Addr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use regex::Regex;
+
+use super::{Check, ReplaceMethod, Request, Response};
+
+const FIRST_NAMES: [&str; 8] = [
+    "alex", "sam", "jordan", "taylor", "casey", "morgan", "riley", "jamie",
+];
+
+const LAST_NAMES: [&str; 8] = [
+    "smith", "lee", "garcia", "brown", "miller", "davis", "lopez", "wilson",
+];
+
+/// Area codes for fake phone numbers, the numbers 555-0100 to 555-0199 are reserved
+/// for fiction in all of them.
+const AREA_CODES: [&str; 6] = ["202", "212", "312", "415", "617", "713"];
+
+/// The kinds of PII found by the [`LocalDetector`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// An email address.
+    Email,
+    /// A phone number in international format, or a formatted national number.
+    Phone,
+    /// A card number with a valid Luhn check digit.
+    CreditCard,
+    /// An IBAN with valid check digits.
+    Iban,
+    /// A US social security number written with separators.
+    Ssn,
+    /// An IPv4 or IPv6 address.
+    IpAddress,
+}
+
+impl Category {
+    /// All of the categories, in the order used to resolve overlapping matches.
+    pub const ALL: [Category; 6] = [
+        Category::Email,
+        Category::Iban,
+        Category::CreditCard,
+        Category::Ssn,
+        Category::IpAddress,
+        Category::Phone,
+    ];
+
+    /// Returns the label used by [`ReplaceMethod::Category`], e.g. `EMAIL_ADDRESS`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Email => "EMAIL_ADDRESS",
+            Category::Phone => "PHONE_NUMBER",
+            Category::CreditCard => "CREDIT_CARD",
+            Category::Iban => "IBAN_CODE",
+            Category::Ssn => "US_SSN",
+            Category::IpAddress => "IP_ADDRESS",
+        }
+    }
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+/// PII found in a text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entity {
+    pub category: Category,
+    /// The byte offset of the first character in the text.
+    pub start: usize,
+    /// The byte offset after the last character in the text.
+    pub end: usize,
+    pub text: String,
+}
+
+/// Finds and replaces PII without calling the API.
+///
+/// The detector only finds PII with a fixed structure, it does not find names or
+/// addresses like the PII endpoint. It can be used as a cheap pre-filter, so the
+/// structured PII never leaves the process, or as a fallback when the API can't be
+/// reached. Numbers are validated with their checksums where there is one, which
+/// keeps the false positives for order numbers, dates and versions low.
+///
+/// # Example
+///
+/// ```ignore
+/// use prediction_guard::pii;
+///
+/// let detector = pii::LocalDetector::new();
+///
+/// let prompt = detector.replace("My card is 4111 1111 1111 1111", pii::ReplaceMethod::Category);
+/// assert_eq!(prompt, "My card is <CREDIT_CARD>");
+///
+/// let req = pii::Request::new(prompt, true, pii::ReplaceMethod::Mask);
+/// let resp = match clt.pii(&req).await {
+///     Ok(resp) => resp,
+///     Err(_) => detector.check(&req),
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalDetector {
+    categories: Vec<Category>,
+}
+
+impl Default for LocalDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalDetector {
+    /// Creates a new detector for all of the categories.
+    pub fn new() -> Self {
+        Self {
+            categories: Category::ALL.to_vec(),
+        }
+    }
+
+    /// Sets the categories to detect.
+    ///
+    /// ## Arguments
+    ///
+    /// * `categories` - The categories of PII to find, all other PII is ignored.
+    pub fn categories(mut self, categories: Vec<Category>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// Returns the PII found in the text, ordered by position. When matches overlap,
+    /// the one from the category first in [`Category::ALL`] is kept.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text to search.
+    pub fn detect(&self, text: &str) -> Vec<Entity> {
+        let mut found: Vec<Entity> = Vec::new();
+
+        for category in Category::ALL {
+            if !self.categories.contains(&category) {
+                continue;
+            }
+
+            for (start, end) in spans(category, text) {
+                if found.iter().any(|e| start < e.end && e.start < end) {
+                    continue;
+                }
+
+                found.push(Entity {
+                    category,
+                    start,
+                    end,
+                    text: text[start..end].to_string(),
+                });
+            }
+        }
+
+        found.sort_by_key(|e| e.start);
+        found
+    }
+
+    /// Returns true if the text contains PII.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text to search.
+    pub fn contains_pii(&self, text: &str) -> bool {
+        !self.detect(text).is_empty()
+    }
+
+    /// Returns the text with all of the PII replaced.
+    ///
+    /// * `Random` replaces each letter and digit with a random one of the same kind.
+    /// * `Mask` replaces each character with `*`.
+    /// * `Category` replaces the PII with its label, e.g. `<EMAIL_ADDRESS>`.
+    /// * `Fake` replaces the PII with a made up value in the same format. Card numbers
+    ///   and IBANs have valid checksums, phone numbers and IP addresses are from the
+    ///   ranges reserved for examples, SSNs are from a range that is never issued.
+    ///   Phone numbers are North American, other numbers only keep their separator.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text to search.
+    /// * `method` - The method used to replace the PII.
+    pub fn replace(&self, text: &str, method: ReplaceMethod) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut last = 0;
+
+        for entity in self.detect(text) {
+            out.push_str(&text[last..entity.start]);
+            out.push_str(&replacement(&entity, method));
+            last = entity.end;
+        }
+
+        out.push_str(&text[last..]);
+        out
+    }
+
+    /// Checks a PII request locally and returns a response in the format of the PII
+    /// endpoint. The new prompt is the unchanged prompt when the request does not
+    /// replace the PII, use [`LocalDetector::detect`] to find it instead.
+    ///
+    /// ## Arguments
+    ///
+    /// * `req` - The request that would be sent to the PII endpoint.
+    pub fn check(&self, req: &Request) -> Response {
+        let new_prompt = if req.replace {
+            self.replace(&req.prompt, req.replace_method)
+        } else {
+            req.prompt.clone()
+        };
+
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Response {
+            id: format!("pii-local-{:016x}", fastrand::u64(..)),
+            object: "pii_check".to_string(),
+            created: created.to_string(),
+            checks: vec![Check {
+                new_prompt,
+                index: 0,
+            }],
+        }
+    }
+}
+
+fn regex(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid pii pattern"))
+}
+
+/// Returns the spans of the valid matches for a category.
+fn spans(category: Category, text: &str) -> Vec<(usize, usize)> {
+    static EMAIL: OnceLock<Regex> = OnceLock::new();
+    static PHONE: OnceLock<Regex> = OnceLock::new();
+    static CARD: OnceLock<Regex> = OnceLock::new();
+    static IBAN: OnceLock<Regex> = OnceLock::new();
+    static SSN: OnceLock<Regex> = OnceLock::new();
+    static IPV4: OnceLock<Regex> = OnceLock::new();
+    static IPV6: OnceLock<Regex> = OnceLock::new();
+
+    match category {
+        Category::Email => valid_spans(
+            regex(&EMAIL, r"(?i)[a-z0-9_.%+-]+@(?:[a-z0-9-]+\.)+[a-z]{2,63}"),
+            text,
+            false,
+            valid_email,
+        ),
+        Category::Phone => valid_spans(
+            regex(
+                &PHONE,
+                r"(?x)
+                \+\d{1,3}(?:[\ .-]?\(?\d{1,4}\)?){1,5}[\ .-]?\d{2,4}
+                | (?:\b1[\ .-]?)?(?:\(\d{3}\)[\ .-]?|\b\d{3}[\ .-])\d{3}[\ .-]\d{4}
+                | \(?\b0\d{1,4}\)?[\ .-]?\d{3,4}[\ .-]?\d{3,4}",
+            ),
+            text,
+            true,
+            valid_phone,
+        ),
+        Category::CreditCard => valid_spans(
+            regex(&CARD, r"\b\d(?:[ -]?\d){12,18}\b"),
+            text,
+            true,
+            valid_card,
+        ),
+        Category::Iban => valid_spans(
+            regex(
+                &IBAN,
+                r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,3})?\b",
+            ),
+            text,
+            true,
+            valid_iban,
+        ),
+        Category::Ssn => valid_spans(
+            regex(&SSN, r"\b\d{3}[- ]\d{2}[- ]\d{4}\b"),
+            text,
+            false,
+            valid_ssn,
+        ),
+        Category::IpAddress => {
+            let mut found = valid_spans(
+                regex(&IPV4, r"\b(?:\d{1,3}\.){3}\d{1,3}\b"),
+                text,
+                false,
+                valid_ipv4,
+            );
+            found.extend(valid_spans(
+                regex(&IPV6, r"(?i)[0-9a-f:]{2,39}"),
+                text,
+                false,
+                valid_ipv6,
+            ));
+            found
+        }
+    }
+}
+
+/// Returns the spans of the matches that are valid and not part of a longer word.
+/// With `shrink`, a match that is not valid is shortened at its separators until a
+/// valid prefix is found, so a trailing number does not hide the PII before it.
+fn valid_spans(
+    re: &Regex,
+    text: &str,
+    shrink: bool,
+    valid: fn(&str, &str, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+
+    for m in re.find_iter(text) {
+        let mut ends = vec![m.end()];
+
+        if shrink {
+            ends.extend(
+                m.as_str()
+                    .char_indices()
+                    .rev()
+                    .filter(|(_, c)| matches!(c, ' ' | '-' | '.'))
+                    .map(|(i, _)| m.start() + i),
+            );
+        }
+
+        let end = ends.into_iter().find(|&end| {
+            isolated(text, m.start(), end) && valid(&text[m.start()..end], text, m.start())
+        });
+
+        if let Some(end) = end {
+            found.push((m.start(), end));
+        }
+    }
+
+    found
+}
+
+/// Returns true if the characters around the span are not letters or digits.
+fn isolated(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+
+    !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+}
+
+fn digits(s: &str) -> Vec<u32> {
+    s.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+/// Returns true if all of the characters that are not digits are the same.
+fn same_separator(s: &str) -> bool {
+    let mut seps = s.chars().filter(|c| !c.is_ascii_digit());
+    match seps.next() {
+        Some(first) => seps.all(|c| c == first),
+        None => true,
+    }
+}
+
+fn valid_email(s: &str, text: &str, start: usize) -> bool {
+    let Some((local, _)) = s.split_once('@') else {
+        return false;
+    };
+
+    let before = text[..start].chars().next_back();
+
+    !local.starts_with('.') && !local.ends_with('.') && !local.contains("..") && before != Some('@')
+}
+
+fn valid_phone(s: &str, text: &str, start: usize) -> bool {
+    let count = digits(s).len();
+
+    if text[..start].ends_with('+') || !s.contains(|c: char| !c.is_ascii_digit()) {
+        return false;
+    }
+
+    if s.starts_with('+') {
+        return (8..=15).contains(&count);
+    }
+
+    (10..=11).contains(&count)
+}
+
+fn valid_card(s: &str, text: &str, start: usize) -> bool {
+    let d = digits(s);
+
+    !text[..start].ends_with('+')
+        && (13..=19).contains(&d.len())
+        && (2..=6).contains(&d[0])
+        && same_separator(s)
+        && card_groups(s)
+        && d.split_last()
+            .is_some_and(|(last, payload)| luhn_digit(payload) == *last)
+}
+
+/// Returns true if the digits are not grouped, or grouped like a printed card, e.g.
+/// 4-4-4-4 or 4-6-5.
+fn card_groups(s: &str) -> bool {
+    let groups: Vec<usize> = s.split([' ', '-']).map(str::len).collect();
+
+    match groups.split_last() {
+        Some((last, init)) if !init.is_empty() => {
+            init.iter().all(|n| *n == 4 || *n == 6) && (3..=6).contains(last)
+        }
+        _ => true,
+    }
+}
+
+/// Returns the Luhn check digit for the digits before it.
+fn luhn_digit(payload: &[u32]) -> u32 {
+    let sum: u32 = payload
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &n)| match i % 2 {
+            0 if n * 2 > 9 => n * 2 - 9,
+            0 => n * 2,
+            _ => n,
+        })
+        .sum();
+
+    (10 - sum % 10) % 10
+}
+
+fn valid_iban(s: &str, _: &str, _: usize) -> bool {
+    let compact: String = s.chars().filter(|c| *c != ' ').collect();
+
+    (15..=34).contains(&compact.len()) && iban_remainder(&compact) == 1
+}
+
+/// Returns the IBAN modulo 97, after the first four characters are moved to the end.
+fn iban_remainder(compact: &str) -> u32 {
+    let (head, tail) = compact.split_at(4);
+
+    tail.chars().chain(head.chars()).fold(0, |acc, c| {
+        let n = c.to_digit(36).unwrap_or_default();
+        match n {
+            0..=9 => (acc * 10 + n) % 97,
+            _ => (acc * 100 + n) % 97,
+        }
+    })
+}
+
+fn valid_ssn(s: &str, _: &str, _: usize) -> bool {
+    if !same_separator(s) {
+        return false;
+    }
+
+    let d: String = s.chars().filter(char::is_ascii_digit).collect();
+    let (area, rest) = d.split_at(3);
+    let (group, serial) = rest.split_at(2);
+
+    area != "000" && area != "666" && !area.starts_with('9') && group != "00" && serial != "0000"
+}
+
+fn valid_ipv4(s: &str, text: &str, start: usize) -> bool {
+    let after = &text[start + s.len()..];
+    let dotted_digit = |s: &str| s.len() > 1 && s.as_bytes()[1].is_ascii_digit();
+
+    if text[..start].ends_with('.') || (after.starts_with('.') && dotted_digit(after)) {
+        return false;
+    }
+
+    s.split('.')
+        .all(|octet| octet.parse::<u8>().is_ok() && (octet == "0" || !octet.starts_with('0')))
+}
+
+fn valid_ipv6(s: &str, _: &str, _: usize) -> bool {
+    s.matches(':').count() >= 2
+        && s.contains(|c: char| c.is_ascii_digit())
+        && s.parse::<Ipv6Addr>().is_ok_and(|ip| !ip.is_unspecified())
+}
+
+fn replacement(entity: &Entity, method: ReplaceMethod) -> String {
+    match method {
+        ReplaceMethod::Mask => "*".repeat(entity.text.chars().count()),
+        ReplaceMethod::Category => format!("<{}>", entity.category.label()),
+        ReplaceMethod::Random => random(&entity.text),
+        ReplaceMethod::Fake => fake(entity),
+    }
+}
+
+/// Replaces each letter and digit with a random one of the same kind.
+fn random(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '0'..='9' => fastrand::digit(10),
+            'a'..='z' => fastrand::lowercase(),
+            'A'..='Z' => fastrand::uppercase(),
+            _ => c,
+        })
+        .collect()
+}
+
+fn random_digits(n: usize) -> String {
+    (0..n).map(|_| fastrand::digit(10)).collect()
+}
+
+/// Joins the digits in groups of four with the separator used in the original.
+fn grouped(compact: &str, original: &str) -> String {
+    let sep = original
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '+');
+
+    match sep {
+        Some(sep) => compact
+            .as_bytes()
+            .chunks(4)
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join(sep.to_string().as_str()),
+        None => compact.to_string(),
+    }
+}
+
+/// Returns a North American number from the range reserved for fiction. A North
+/// American original keeps its layout, other numbers keep their separator.
+fn fake_phone(original: &str) -> String {
+    let area = AREA_CODES[fastrand::usize(..AREA_CODES.len())];
+    let number = format!("1{}55501{}", area, random_digits(2));
+
+    let country = original.trim_start_matches(['+', '(']).starts_with('1');
+    let fill = match digits(original).len() {
+        10 => &number[1..],
+        11 if country => &number[..],
+        _ => {
+            return match original.chars().find(|c| matches!(c, ' ' | '-' | '.')) {
+                Some(sep) => format!("+1{sep}{}{sep}555{sep}{}", area, &number[7..]),
+                None => format!("+{}", number),
+            };
+        }
+    };
+
+    let mut fill = fill.chars();
+    original
+        .chars()
+        .map(|c| match c.is_ascii_digit() {
+            true => fill.next().unwrap_or(c),
+            false => c,
+        })
+        .collect()
+}
+
+fn fake(entity: &Entity) -> String {
+    let text = &entity.text;
+
+    match entity.category {
+        Category::Email => format!(
+            "{}.{}@example.com",
+            FIRST_NAMES[fastrand::usize(..FIRST_NAMES.len())],
+            LAST_NAMES[fastrand::usize(..LAST_NAMES.len())]
+        ),
+        Category::Phone => fake_phone(text),
+        Category::CreditCard => {
+            let payload = format!("4{}", random_digits(14));
+            let check = luhn_digit(&digits(&payload));
+
+            grouped(&format!("{}{}", payload, check), text)
+        }
+        Category::Iban => {
+            let bban = random_digits(18);
+            let check = 98 - iban_remainder(&format!("DE00{}", bban));
+
+            grouped(&format!("DE{:02}{}", check, bban), text)
+        }
+        Category::Ssn => {
+            let sep = if text.contains(' ') { " " } else { "-" };
+            format!(
+                "9{}{sep}{}{sep}{}",
+                random_digits(2),
+                random_digits(2),
+                random_digits(4)
+            )
+        }
+        Category::IpAddress if text.contains(':') => {
+            format!("2001:db8::{:x}", fastrand::u16(1..))
+        }
+        Category::IpAddress => format!("192.0.2.{}", fastrand::u8(1..255)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(text: &str) -> Vec<(Category, String)> {
+        LocalDetector::new()
+            .detect(text)
+            .into_iter()
+            .map(|e| (e.category, e.text))
+            .collect()
+    }
+
+    fn assert_corpus(category: Category, positives: &[(&str, &str)], negatives: &[&str]) {
+        for (text, pii) in positives {
+            assert_eq!(
+                found(text),
+                vec![(category, pii.to_string())],
+                "expected {} in {:?}",
+                category,
+                text
+            );
+        }
+
+        for text in negatives {
+            let wrong: Vec<_> = found(text)
+                .into_iter()
+                .filter(|(c, _)| *c == category)
+                .collect();
+            assert!(wrong.is_empty(), "unexpected {:?} in {:?}", wrong, text);
+        }
+    }
+
+    #[test]
+    fn emails() {
+        assert_corpus(
+            Category::Email,
+            &[
+                ("My email is jane@example.com.", "jane@example.com"),
+                (
+                    "write to John.Doe+news@mail.example.co.uk",
+                    "John.Doe+news@mail.example.co.uk",
+                ),
+                ("<ops_team@corp-mail.io>", "ops_team@corp-mail.io"),
+                ("(a1@b2.org)", "a1@b2.org"),
+                ("contact:x%y@domain.travel, thanks", "x%y@domain.travel"),
+            ],
+            &[
+                "jane@localhost",
+                "@example.com",
+                "jane@.com",
+                ".jane@example.com",
+                "jane.@example.com",
+                "jane..doe@example.com",
+                "the price is 5@3.50",
+                "follow @predictionguard on social",
+            ],
+        );
+    }
+
+    #[test]
+    fn phones() {
+        assert_corpus(
+            Category::Phone,
+            &[
+                ("call 555-123-4567 today", "555-123-4567"),
+                ("call (555) 123-4567", "(555) 123-4567"),
+                ("call (555)123-4567", "(555)123-4567"),
+                ("call 555.123.4567", "555.123.4567"),
+                ("call 1-800-555-0199", "1-800-555-0199"),
+                ("call +1 (555) 123-4567", "+1 (555) 123-4567"),
+                ("call +44 20 7946 0958", "+44 20 7946 0958"),
+                ("call +4915112345678", "+4915112345678"),
+                ("call +33 1 23 45 67 89", "+33 1 23 45 67 89"),
+                ("call 020 7946 0958", "020 7946 0958"),
+                ("call (020) 7946 0958", "(020) 7946 0958"),
+                ("call 555-123-4567 12 times", "555-123-4567"),
+            ],
+            &[
+                "order 5551234567 shipped",
+                "call 555-1234",
+                "on 2024-01-15",
+                "the years 2024 2025 2026",
+                "at 12:30:45",
+                "version 1.2.3",
+                "id 555-123-45678",
+                "code +123",
+                "ref A555-123-4567",
+            ],
+        );
+    }
+
+    #[test]
+    fn credit_cards() {
+        assert_corpus(
+            Category::CreditCard,
+            &[
+                ("visa 4111111111111111", "4111111111111111"),
+                ("visa 4111 1111 1111 1111.", "4111 1111 1111 1111"),
+                ("mc 5555-5555-5555-4444", "5555-5555-5555-4444"),
+                ("amex 378282246310005", "378282246310005"),
+                ("amex 3782 822463 10005", "3782 822463 10005"),
+                ("discover 6011111111111117", "6011111111111117"),
+                ("mc 2223003122003222", "2223003122003222"),
+                ("card 4111 1111 1111 1111 42 times", "4111 1111 1111 1111"),
+            ],
+            &[
+                "visa 4111111111111112",
+                "id 1111111111111117",
+                "id 9111111111111115",
+                "mixed 4111-1111 1111-1111",
+                "grouped 41111 1111 1111 111",
+                "short 4111111111",
+                "long 41111111111111111111111",
+                "ref X4111111111111111",
+            ],
+        );
+    }
+
+    #[test]
+    fn ibans() {
+        assert_corpus(
+            Category::Iban,
+            &[
+                ("iban DE89370400440532013000", "DE89370400440532013000"),
+                (
+                    "iban DE89 3704 0044 0532 0130 00",
+                    "DE89 3704 0044 0532 0130 00",
+                ),
+                (
+                    "iban GB82 WEST 1234 5698 7654 32.",
+                    "GB82 WEST 1234 5698 7654 32",
+                ),
+                (
+                    "iban FR1420041010050500013M02606",
+                    "FR1420041010050500013M02606",
+                ),
+                ("iban NL91ABNA0417164300", "NL91ABNA0417164300"),
+                ("iban BE68539007547034", "BE68539007547034"),
+                (
+                    "iban DE89 3704 0044 0532 0130 00 THE END",
+                    "DE89 3704 0044 0532 0130 00",
+                ),
+            ],
+            &[
+                "iban DE88370400440532013000",
+                "iban DE89 3704 0044 0532 0130 01",
+                "code AB12CD34",
+                "ABCD EFGH IJKL MNOP",
+            ],
+        );
+    }
+
+    #[test]
+    fn ssns() {
+        assert_corpus(
+            Category::Ssn,
+            &[
+                ("ssn 123-45-6789", "123-45-6789"),
+                ("ssn 123 45 6789.", "123 45 6789"),
+                ("ssn: 078-05-1120", "078-05-1120"),
+            ],
+            &[
+                "ssn 000-12-3456",
+                "ssn 666-12-3456",
+                "ssn 900-12-3456",
+                "ssn 123-00-4567",
+                "ssn 123-45-0000",
+                "ssn 123-45 6789",
+                "ssn 123456789",
+                "id 1123-45-6789",
+            ],
+        );
+    }
+
+    #[test]
+    fn ip_addresses() {
+        assert_corpus(
+            Category::IpAddress,
+            &[
+                ("host 192.168.1.1", "192.168.1.1"),
+                ("host 10.0.0.255.", "10.0.0.255"),
+                ("from (8.8.8.8)", "8.8.8.8"),
+                ("host 2001:db8::1", "2001:db8::1"),
+                ("host fe80::1ff:fe23:4567:890a", "fe80::1ff:fe23:4567:890a"),
+                (
+                    "host 2001:0db8:85a3:0000:0000:8a2e:0370:7334",
+                    "2001:0db8:85a3:0000:0000:8a2e:0370:7334",
+                ),
+                ("loopback ::1", "::1"),
+            ],
+            &[
+                "host 256.1.1.1",
+                "version 1.2.3.4.5",
+                "host 192.168.01.1",
+                "version 1.2.3",
+                "use std::io",
+                "at 12:30:45",
+                "mac 00:1a:2b:3c:4d:5e",
+                "cafe::beef",
+                "any ::",
+            ],
+        );
+    }
+
+    #[test]
+    fn mixed_text() {
+        let text = "Jane (jane@example.com, +1 555-123-4567) paid with 4111 1111 1111 1111 \
+                    from 192.168.1.1, ssn 123-45-6789, iban DE89370400440532013000.";
+
+        assert_eq!(
+            found(text),
+            vec![
+                (Category::Email, "jane@example.com".to_string()),
+                (Category::Phone, "+1 555-123-4567".to_string()),
+                (Category::CreditCard, "4111 1111 1111 1111".to_string()),
+                (Category::IpAddress, "192.168.1.1".to_string()),
+                (Category::Ssn, "123-45-6789".to_string()),
+                (Category::Iban, "DE89370400440532013000".to_string()),
+            ]
+        );
+
+        let clean = "The meeting on 2024-01-15 at 10:30 covers release 1.2.3 and order 5551234567.";
+        assert!(!LocalDetector::new().contains_pii(clean));
+
+        let only_email = LocalDetector::new().categories(vec![Category::Email]);
+        assert_eq!(only_email.detect(text).len(), 1);
+    }
+
+    #[test]
+    fn replace_methods() {
+        let detector = LocalDetector::new();
+        let text = "Mail jane@example.com or call 555-123-4567.";
+
+        assert_eq!(
+            detector.replace(text, ReplaceMethod::Category),
+            "Mail <EMAIL_ADDRESS> or call <PHONE_NUMBER>."
+        );
+        assert_eq!(
+            detector.replace(text, ReplaceMethod::Mask),
+            "Mail **************** or call ************."
+        );
+
+        let random = detector.replace(text, ReplaceMethod::Random);
+        let shape = |s: &str| {
+            s.chars()
+                .map(|c| match c {
+                    '0'..='9' => '9',
+                    'a'..='z' => 'a',
+                    'A'..='Z' => 'A',
+                    _ => c,
+                })
+                .collect::<String>()
+        };
+        assert_eq!(shape(&random), shape(text));
+        assert!(random.starts_with("Mail ") && random.ends_with('.'));
+
+        let fake = detector.replace(text, ReplaceMethod::Fake);
+        let entities = detector.detect(&fake);
+        assert_eq!(entities.len(), 2, "{}", fake);
+        assert!(entities[0].text.ends_with("@example.com"));
+        assert_eq!(&entities[1].text[3..10], "-555-01", "{}", fake);
+    }
+
+    #[test]
+    fn fake_values() {
+        let detector = LocalDetector::new();
+
+        for _ in 0..50 {
+            for text in [
+                "4111 1111 1111 1111",
+                "DE89 3704 0044 0532 0130 00",
+                "192.168.1.1",
+                "2001:db8::1",
+            ] {
+                let fake = detector.replace(text, ReplaceMethod::Fake);
+                let entities = detector.detect(&fake);
+
+                assert_eq!(entities.len(), 1, "{} -> {}", text, fake);
+                assert_eq!(entities[0].text, fake);
+                assert_eq!(
+                    entities[0].category,
+                    detector.detect(text)[0].category,
+                    "{}",
+                    fake
+                );
+            }
+
+            let card = detector.replace("5555-5555-5555-4444", ReplaceMethod::Fake);
+            assert_eq!(card.matches('-').count(), 3);
+
+            for (text, layout) in [
+                ("555-123-4567", "999-999-9999"),
+                ("(555) 123-4567", "(999) 999-9999"),
+                ("+1 (555) 123-4567", "+9 (999) 999-9999"),
+                ("1-800-555-0199", "9-999-999-9999"),
+                ("+44 20 7946 0958", "+9 999 999 9999"),
+                ("+33 1 23 45 67 89", "+9 999 999 9999"),
+                ("+4915112345678", "+99999999999"),
+            ] {
+                let phone = detector.replace(text, ReplaceMethod::Fake);
+                let shape: String = phone
+                    .chars()
+                    .map(|c| if c.is_ascii_digit() { '9' } else { c })
+                    .collect();
+
+                assert_eq!(shape, layout, "{} -> {}", text, phone);
+                assert!(phone.contains("555"), "{}", phone);
+                assert_eq!(
+                    found(&format!("call {}", phone)),
+                    vec![(Category::Phone, phone.clone())]
+                );
+            }
+
+            let ssn = detector.replace("123-45-6789", ReplaceMethod::Fake);
+            assert!(ssn.starts_with('9') && ssn.len() == 11);
+            assert!(!detector.contains_pii(&ssn));
+        }
+    }
+
+    #[test]
+    fn check_request() {
+        let detector = LocalDetector::new();
+
+        let req = Request::new(
+            "My email is jane@example.com".to_string(),
+            true,
+            ReplaceMethod::Category,
+        );
+        let resp = detector.check(&req);
+        assert_eq!(resp.checks.len(), 1);
+        assert_eq!(resp.checks[0].new_prompt, "My email is <EMAIL_ADDRESS>");
+        assert_eq!(resp.object, "pii_check");
+
+        let req = Request::new(
+            "My email is jane@example.com".to_string(),
+            false,
+            ReplaceMethod::Category,
+        );
+        assert_eq!(
+            detector.check(&req).checks[0].new_prompt,
+            "My email is jane@example.com"
+        );
+    }
+}
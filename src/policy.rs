@@ -0,0 +1,977 @@
+//! Declarative guardrail policies loaded from YAML or TOML files, enabled with the
+//! `policy` feature.
+//!
+//! A policy is a list of rules. Each rule selects models and routes with patterns,
+//! where `*` matches any characters, and configures the [`Guard`] checks that apply to
+//! them. The first rule that matches a call is used, calls that match no rule are sent
+//! without checks. A [`PolicyStore`] reloads the policy when the file changes, so the
+//! thresholds can be changed without restarting the application.
+//!
+//! ```yaml
+//! version: 1
+//! rules:
+//!   - name: support
+//!     models: ["Hermes-3-*"]
+//!     routes: ["/support/*"]
+//!     injection:
+//!       threshold: 0.5
+//!     pii:
+//!       action: replace
+//!       replace_method: category
+//!     toxicity:
+//!       threshold: 0.7
+//!   - name: default
+//!     pii:
+//!       action: block
+//! ```
+//!
+//! The same policy in TOML:
+//!
+//! ```toml
+//! version = 1
+//!
+//! [[rules]]
+//! name = "support"
+//! models = ["Hermes-3-*"]
+//! routes = ["/support/*"]
+//! injection = { threshold = 0.5 }
+//! pii = { action = "replace", replace_method = "category" }
+//! toxicity = { threshold = 0.7 }
+//!
+//! [[rules]]
+//! name = "default"
+//! pii = { action = "block" }
+//! ```
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{chat, client, policy};
+//!
+//! let clt = client::Client::new()?;
+//! let store = policy::PolicyStore::open("guardrails.yaml").await?;
+//! tokio::spawn(store.clone().watch(std::time::Duration::from_secs(10)));
+//!
+//! let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+//!     .add_message(chat::Roles::User, "How do I reset my password?".to_string());
+//!
+//! let ctx = policy::Context::new().route("/support/chat".to_string());
+//!
+//! match store.policy().generate_chat_completion(&clt, &req, &ctx).await? {
+//!     policy::Outcome::Completed { response, .. } => println!("{:?}", response),
+//!     policy::Outcome::PromptBlocked(report) => println!("prompt blocked: {:?}", report.verdict),
+//!     policy::Outcome::OutputBlocked { output, .. } => println!("output blocked: {:?}", output.verdict),
+//! }
+//! ```
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::{
+    chat,
+    client::Client,
+    completion,
+    guard::{Guard, Report, Scores, Verdict},
+    pii, PgError, Result,
+};
+
+/// The version of the policy format.
+pub const VERSION: u32 = 1;
+
+/// The format of a policy file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+}
+
+impl Format {
+    /// Returns the format for the extension of the path, `yaml`, `yml` or `toml`.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The path of the policy file.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+
+        match ext.as_str() {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            _ => None,
+        }
+    }
+}
+
+/// A threshold from 0.0 to 1.0, values outside of the range are rejected when the
+/// policy is parsed.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Deserialize, Serialize)]
+#[serde(try_from = "f64", into = "f64")]
+pub struct Threshold(f64);
+
+impl Threshold {
+    /// Returns the value of the threshold.
+    pub fn value(&self) -> f64 {
+        self.0
+    }
+}
+
+impl TryFrom<f64> for Threshold {
+    type Error = String;
+
+    fn try_from(value: f64) -> std::result::Result<Self, Self::Error> {
+        if !(0.0..=1.0).contains(&value) {
+            return Err(format!(
+                "threshold must be between 0.0 and 1.0, got {}",
+                value
+            ));
+        }
+
+        Ok(Threshold(value))
+    }
+}
+
+impl From<Threshold> for f64 {
+    fn from(t: Threshold) -> Self {
+        t.0
+    }
+}
+
+/// A check that blocks the text when its score reaches the threshold.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScoreRule {
+    pub threshold: Threshold,
+}
+
+/// The PII check of a rule.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct PiiRule {
+    /// Blocks the prompt or replaces the PII.
+    pub action: pii::InputMethod,
+    /// The method used to replace the PII.
+    #[serde(default)]
+    pub replace_method: pii::ReplaceMethod,
+}
+
+/// The factuality check of a rule. The output is blocked if its score is below the
+/// threshold for every reference.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FactualityRule {
+    pub threshold: Threshold,
+    /// References used for every call, in addition to the references of the [`Context`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<String>,
+}
+
+/// The checks that apply to the models and routes matched by the rule.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// The name of the rule, used in the logs.
+    pub name: String,
+    /// The model patterns, all models are matched if empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// The route patterns, all routes are matched if empty. A rule with routes does
+    /// not match calls without a route.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub injection: Option<ScoreRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pii: Option<PiiRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toxicity: Option<ScoreRule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub factuality: Option<FactualityRule>,
+}
+
+impl Rule {
+    /// Returns true if the rule applies to the model and route.
+    ///
+    /// ## Arguments
+    ///
+    /// * `model` - The model of the call.
+    /// * `route` - The route of the call, if there is one.
+    pub fn matches(&self, model: &str, route: Option<&str>) -> bool {
+        let model_matches =
+            self.models.is_empty() || self.models.iter().any(|p| wildcard(p, model));
+
+        let route_matches = self.routes.is_empty()
+            || route.is_some_and(|route| self.routes.iter().any(|p| wildcard(p, route)));
+
+        model_matches && route_matches
+    }
+
+    /// Returns a guard with the checks of the rule. The factuality check is skipped if
+    /// there are no references.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the check endpoints.
+    /// * `references` - The references added to the references of the rule.
+    pub fn guard(&self, client: Client, references: &[String]) -> Guard {
+        let mut guard = Guard::new(client);
+
+        if let Some(check) = &self.injection {
+            guard = guard.injection(check.threshold.value());
+        }
+
+        if let Some(check) = &self.pii {
+            guard = guard.pii(check.action, check.replace_method);
+        }
+
+        if let Some(check) = &self.toxicity {
+            guard = guard.toxicity(check.threshold.value());
+        }
+
+        if let Some(check) = &self.factuality {
+            let mut refs = check.references.clone();
+            refs.extend_from_slice(references);
+
+            if !refs.is_empty() {
+                guard = guard.factuality(refs, check.threshold.value());
+            }
+        }
+
+        guard
+    }
+}
+
+/// The details of a call used to select the rule and run the checks.
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    route: Option<String>,
+    references: Vec<String>,
+}
+
+impl Context {
+    /// Creates a context without a route and references.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the route of the call, e.g. the path of the HTTP handler that makes it.
+    ///
+    /// ## Arguments
+    ///
+    /// * `route` - The route matched against the route patterns of the rules.
+    pub fn route(mut self, route: String) -> Self {
+        self.route = Some(route);
+        self
+    }
+
+    /// Sets the references for the factuality check.
+    ///
+    /// ## Arguments
+    ///
+    /// * `references` - The reference texts.
+    pub fn references(mut self, references: Vec<String>) -> Self {
+        self.references = references;
+        self
+    }
+}
+
+/// The result of a call made with a policy.
+#[derive(Debug, Clone)]
+pub enum Outcome<T> {
+    /// The request was sent and the output passed the checks. The reports are `None`
+    /// if no rule matched the call.
+    Completed {
+        response: T,
+        prompt: Option<Report>,
+        output: Option<Report>,
+    },
+    /// The prompt was blocked and the request was not sent.
+    PromptBlocked(Report),
+    /// The output of the model was blocked.
+    OutputBlocked {
+        response: T,
+        prompt: Report,
+        output: Report,
+    },
+}
+
+impl<T> Outcome<T> {
+    /// Returns the response if the call completed.
+    pub fn response(self) -> Option<T> {
+        match self {
+            Outcome::Completed { response, .. } => Some(response),
+            _ => None,
+        }
+    }
+}
+
+/// A guardrail policy, see the [module documentation](self) for the format.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(deserialize_with = "deserialize_version")]
+    pub version: u32,
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+impl Policy {
+    /// Parses a policy from YAML.
+    ///
+    /// ## Arguments
+    ///
+    /// * `s` - The YAML policy.
+    ///
+    /// Returns a [`PgError::Policy`] with the line and column of the error if the
+    /// policy is invalid.
+    pub fn from_yaml(s: &str) -> Result<Policy> {
+        serde_yaml::from_str(s).map_err(|e| {
+            let location = e.location().map(|l| (l.line(), l.column()));
+            let mut message = e.to_string();
+
+            // The message ends with the location, which is reported separately.
+            if let Some((line, column)) = location {
+                let suffix = format!(" at line {} column {}", line, column);
+                if let Some(m) = message.strip_suffix(&suffix) {
+                    message = m.to_string();
+                }
+            }
+
+            policy_error(location, message)
+        })
+    }
+
+    /// Parses a policy from TOML.
+    ///
+    /// ## Arguments
+    ///
+    /// * `s` - The TOML policy.
+    ///
+    /// Returns a [`PgError::Policy`] with the line and column of the error if the
+    /// policy is invalid.
+    pub fn from_toml(s: &str) -> Result<Policy> {
+        toml::from_str(s).map_err(|e| {
+            let location = e.span().map(|span| line_column(s, span.start));
+            policy_error(location, e.message().to_string())
+        })
+    }
+
+    /// Parses a policy in the format.
+    ///
+    /// ## Arguments
+    ///
+    /// * `s` - The policy.
+    /// * `format` - The format of the policy.
+    pub fn parse(s: &str, format: Format) -> Result<Policy> {
+        match format {
+            Format::Yaml => Self::from_yaml(s),
+            Format::Toml => Self::from_toml(s),
+        }
+    }
+
+    /// Reads a policy from a file, the format is selected by the extension.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The path of the policy file.
+    pub async fn from_path<P: AsRef<Path>>(path: P) -> Result<Policy> {
+        let path = path.as_ref();
+
+        let format = Format::from_path(path).ok_or_else(|| {
+            PgError::Config(format!(
+                "unknown policy format for {}, expected a yaml, yml or toml extension",
+                path.display()
+            ))
+        })?;
+
+        let s = tokio::fs::read_to_string(path).await?;
+
+        Self::parse(&s, format).map_err(|e| match e {
+            PgError::Policy {
+                line,
+                column,
+                message,
+                ..
+            } => PgError::Policy {
+                path: Some(path.to_path_buf()),
+                line,
+                column,
+                message,
+            },
+            e => e,
+        })
+    }
+
+    /// Returns the first rule that matches the model and route.
+    ///
+    /// ## Arguments
+    ///
+    /// * `model` - The model of the call.
+    /// * `route` - The route of the call, if there is one.
+    pub fn rule_for(&self, model: &str, route: Option<&str>) -> Option<&Rule> {
+        self.rules.iter().find(|r| r.matches(model, route))
+    }
+
+    /// Calls the chat completion endpoint with the checks of the matching rule. The
+    /// last user message is checked before the request is sent and replaced if it
+    /// contains PII, the first choice of the response is checked after.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to send the request and the checks.
+    /// * `req` - An instance of [`chat::Request`].
+    /// * `ctx` - The route and references of the call.
+    ///
+    /// Returns an error if the request or any of the checks fail.
+    pub async fn generate_chat_completion(
+        &self,
+        client: &Client,
+        req: &chat::Request<chat::Message>,
+        ctx: &Context,
+    ) -> Result<Outcome<chat::Response>> {
+        let Some(rule) = self.rule_for(&req.model, ctx.route.as_deref()) else {
+            let response = client.generate_chat_completion(req).await?;
+            return Ok(Outcome::Completed {
+                response,
+                prompt: None,
+                output: None,
+            });
+        };
+
+        let guard = rule.guard(client.clone(), &ctx.references);
+
+        let last_user = req
+            .messages
+            .iter()
+            .rposition(|m| m.role == chat::Roles::User);
+
+        let prompt = match last_user {
+            Some(i) => {
+                guard
+                    .check_prompt(req.messages[i].content.text().into_owned())
+                    .await?
+            }
+            None => allowed(),
+        };
+
+        let redacted;
+        let req = match (&prompt.verdict, last_user) {
+            (Verdict::Blocked { reason, .. }, _) => {
+                warn!(
+                    "prompt blocked by the {} check of policy rule {}",
+                    reason, rule.name
+                );
+                return Ok(Outcome::PromptBlocked(prompt));
+            }
+            (Verdict::Redacted(text), Some(i)) => {
+                let mut r = req.clone();
+                redact(&mut r.messages[i].content, text);
+                redacted = r;
+                &redacted
+            }
+            _ => req,
+        };
+
+        let response = client.generate_chat_completion(req).await?;
+
+        let text = response
+            .choices
+            .first()
+            .map(|c| c.message.content.text().into_owned())
+            .unwrap_or_default();
+
+        let output = guard.check_output(text).await?;
+
+        Ok(outcome(rule, response, prompt, output))
+    }
+
+    /// Calls the completion endpoint with the checks of the matching rule. The prompt
+    /// is checked before the request is sent and replaced if it contains PII, the first
+    /// choice of the response is checked after.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to send the request and the checks.
+    /// * `req` - An instance of [`completion::Request`].
+    /// * `ctx` - The route and references of the call.
+    ///
+    /// Returns an error if the request or any of the checks fail.
+    pub async fn generate_completion(
+        &self,
+        client: &Client,
+        req: &completion::Request,
+        ctx: &Context,
+    ) -> Result<Outcome<completion::Response>> {
+        let Some(rule) = self.rule_for(&req.model, ctx.route.as_deref()) else {
+            let response = client.generate_completion(req).await?;
+            return Ok(Outcome::Completed {
+                response,
+                prompt: None,
+                output: None,
+            });
+        };
+
+        let guard = rule.guard(client.clone(), &ctx.references);
+
+        let prompt = guard.check_prompt(req.prompt.clone()).await?;
+
+        let redacted;
+        let req = match &prompt.verdict {
+            Verdict::Blocked { reason, .. } => {
+                warn!(
+                    "prompt blocked by the {} check of policy rule {}",
+                    reason, rule.name
+                );
+                return Ok(Outcome::PromptBlocked(prompt));
+            }
+            Verdict::Redacted(text) => {
+                redacted = completion::Request {
+                    prompt: text.clone(),
+                    ..req.clone()
+                };
+                &redacted
+            }
+            Verdict::Allowed => req,
+        };
+
+        let response = client.generate_completion(req).await?;
+
+        let text = response
+            .choices
+            .first()
+            .map(|c| c.text.clone())
+            .unwrap_or_default();
+
+        let output = guard.check_output(text).await?;
+
+        Ok(outcome(rule, response, prompt, output))
+    }
+}
+
+/// Holds a policy loaded from a file and reloads it when the file changes.
+///
+/// The store is cheap to clone, all clones share the same policy.
+#[derive(Debug, Clone)]
+pub struct PolicyStore {
+    inner: Arc<StoreInner>,
+}
+
+#[derive(Debug)]
+struct StoreInner {
+    path: PathBuf,
+    policy: RwLock<Arc<Policy>>,
+    /// The modification time and size of the file when it was last read.
+    loaded: Mutex<Option<(SystemTime, u64)>>,
+}
+
+impl PolicyStore {
+    /// Loads the policy from a file.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - The path of the policy file, the format is selected by the extension.
+    ///
+    /// Returns an error if the file can not be read or the policy is invalid.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let stamp = file_stamp(&path).await?;
+        let policy = Policy::from_path(&path).await?;
+
+        Ok(Self {
+            inner: Arc::new(StoreInner {
+                path,
+                policy: RwLock::new(Arc::new(policy)),
+                loaded: Mutex::new(Some(stamp)),
+            }),
+        })
+    }
+
+    /// Returns the path of the policy file.
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Returns the current policy.
+    pub fn policy(&self) -> Arc<Policy> {
+        self.inner
+            .policy
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Reads the policy again if the file changed since it was last read.
+    ///
+    /// Returns true if the policy was replaced. If the new policy is invalid an error
+    /// is returned and the current policy is kept, the file is not read again until it
+    /// changes.
+    pub async fn reload(&self) -> Result<bool> {
+        let stamp = file_stamp(&self.inner.path).await?;
+
+        {
+            let mut loaded = self.inner.loaded.lock().unwrap_or_else(|e| e.into_inner());
+            if *loaded == Some(stamp) {
+                return Ok(false);
+            }
+            *loaded = Some(stamp);
+        }
+
+        let policy = Policy::from_path(&self.inner.path).await?;
+
+        *self.inner.policy.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(policy);
+
+        Ok(true)
+    }
+
+    /// Checks the file for changes at every interval and reloads the policy. Errors
+    /// are logged and the current policy is kept. The future never completes, spawn it
+    /// as a task.
+    ///
+    /// ## Arguments
+    ///
+    /// * `interval` - The time between two checks of the file.
+    pub async fn watch(self, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            match self.reload().await {
+                Ok(true) => info!("reloaded policy {}", self.inner.path.display()),
+                Ok(false) => {}
+                Err(e) => warn!("keeping the current policy, reload failed: {}", e),
+            }
+        }
+    }
+}
+
+async fn file_stamp(path: &Path) -> Result<(SystemTime, u64)> {
+    let metadata = tokio::fs::metadata(path).await?;
+
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+fn deserialize_version<'de, D>(deserializer: D) -> std::result::Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let version = u32::deserialize(deserializer)?;
+
+    if version != VERSION {
+        return Err(de::Error::custom(format!(
+            "unsupported policy version {}, expected {}",
+            version, VERSION
+        )));
+    }
+
+    Ok(version)
+}
+
+fn policy_error(location: Option<(usize, usize)>, message: String) -> PgError {
+    PgError::Policy {
+        path: None,
+        line: location.map(|(l, _)| l),
+        column: location.map(|(_, c)| c),
+        message,
+    }
+}
+
+/// Returns the line and column, starting at 1, of a byte offset.
+fn line_column(s: &str, offset: usize) -> (usize, usize) {
+    let before = &s[..offset.min(s.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|l| l.chars().count())
+        .unwrap_or(0)
+        + 1;
+
+    (line, column)
+}
+
+/// Matches a pattern where `*` matches any characters, including none.
+fn wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // `split` always returns at least one part.
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Replaces the text of a message with the redacted text. For content with parts the
+/// text parts are replaced with a single part at the position of the first one.
+fn redact(content: &mut chat::MessageContent, text: &str) {
+    match content {
+        chat::MessageContent::Text(s) => *s = text.to_string(),
+        chat::MessageContent::Parts(parts) => {
+            let first = parts.iter().position(|p| p.text.is_some());
+            parts.retain(|p| p.text.is_none());
+            parts.insert(
+                first.unwrap_or(0).min(parts.len()),
+                chat::Content::text(text.to_string()),
+            );
+        }
+    }
+}
+
+fn allowed() -> Report {
+    Report {
+        verdict: Verdict::Allowed,
+        scores: Scores::default(),
+    }
+}
+
+fn outcome<T>(rule: &Rule, response: T, prompt: Report, output: Report) -> Outcome<T> {
+    if let Verdict::Blocked { reason, .. } = &output.verdict {
+        warn!(
+            "output blocked by the {} check of policy rule {}",
+            reason, rule.name
+        );
+
+        return Outcome::OutputBlocked {
+            response,
+            prompt,
+            output,
+        };
+    }
+
+    Outcome::Completed {
+        response,
+        prompt: Some(prompt),
+        output: Some(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+version: 1
+rules:
+  - name: support
+    models: ["Hermes-3-*"]
+    routes: ["/support/*"]
+    injection:
+      threshold: 0.5
+    pii:
+      action: replace
+      replace_method: category
+    toxicity:
+      threshold: 0.7
+  - name: default
+    pii:
+      action: block
+"#;
+
+    const TOML: &str = r#"
+version = 1
+
+[[rules]]
+name = "support"
+models = ["Hermes-3-*"]
+routes = ["/support/*"]
+injection = { threshold = 0.5 }
+pii = { action = "replace", replace_method = "category" }
+toxicity = { threshold = 0.7 }
+
+[[rules]]
+name = "default"
+pii = { action = "block" }
+"#;
+
+    fn location(err: PgError) -> (Option<usize>, Option<usize>, String) {
+        match err {
+            PgError::Policy {
+                line,
+                column,
+                message,
+                ..
+            } => (line, column, message),
+            e => panic!("expected a policy error, got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn parse_formats() {
+        let yaml = Policy::from_yaml(YAML).expect("yaml");
+        let toml = Policy::from_toml(TOML).expect("toml");
+        assert_eq!(yaml, toml);
+
+        assert_eq!(yaml.rules.len(), 2);
+        let rule = &yaml.rules[0];
+        assert_eq!(
+            rule.injection.as_ref().map(|c| c.threshold.value()),
+            Some(0.5)
+        );
+        assert_eq!(
+            rule.pii,
+            Some(PiiRule {
+                action: pii::InputMethod::Replace,
+                replace_method: pii::ReplaceMethod::Category,
+            })
+        );
+        assert_eq!(
+            yaml.rules[1].pii.as_ref().map(|p| p.replace_method),
+            Some(pii::ReplaceMethod::Random)
+        );
+
+        let yaml_again = serde_yaml::to_string(&yaml).expect("serialize");
+        assert_eq!(Policy::from_yaml(&yaml_again).expect("round trip"), yaml);
+
+        assert_eq!(
+            Format::from_path(Path::new("policy.YML")),
+            Some(Format::Yaml)
+        );
+        assert_eq!(
+            Format::from_path(Path::new("policy.toml")),
+            Some(Format::Toml)
+        );
+        assert_eq!(Format::from_path(Path::new("policy.json")), None);
+    }
+
+    #[test]
+    fn error_locations() {
+        let yaml = "version: 1\nrules:\n  - name: strict\n    toxicity:\n      threshold: 1.5\n";
+        let (line, column, message) = location(Policy::from_yaml(yaml).expect_err("threshold"));
+        assert_eq!((line, column), (Some(5), Some(7)));
+        assert!(
+            message.contains("threshold must be between 0.0 and 1.0, got 1.5"),
+            "{}",
+            message
+        );
+        assert!(!message.contains("at line"), "{}", message);
+
+        let yaml = "version: 1\nrules:\n  - name: strict\n    toxicty:\n      threshold: 0.5\n";
+        let (line, _, message) = location(Policy::from_yaml(yaml).expect_err("unknown field"));
+        assert_eq!(line, Some(4));
+        assert!(message.contains("unknown field `toxicty`"), "{}", message);
+
+        let toml = "version = 1\n\n[[rules]]\nname = \"strict\"\ntoxicity = { threshold = -0.1 }\n";
+        let (line, column, message) = location(Policy::from_toml(toml).expect_err("threshold"));
+        assert_eq!(line, Some(5));
+        assert!(column.is_some_and(|c| c > 1));
+        assert!(
+            message.contains("threshold must be between 0.0 and 1.0"),
+            "{}",
+            message
+        );
+
+        let toml = "version = 1\n\n[[rules]]\nname = \"strict\"\npii = { action = \"hide\" }\n";
+        let (line, _, message) = location(Policy::from_toml(toml).expect_err("action"));
+        assert_eq!(line, Some(5));
+        assert!(message.contains("hide"), "{}", message);
+
+        let (line, _, message) = location(Policy::from_yaml("version: 2\n").expect_err("version"));
+        assert_eq!(line, Some(1));
+        assert!(
+            message.contains("unsupported policy version 2"),
+            "{}",
+            message
+        );
+
+        let err = Policy::from_toml("rules = []\n").expect_err("missing version");
+        assert!(err.to_string().starts_with("invalid policy"), "{}", err);
+    }
+
+    #[test]
+    fn rule_matching() {
+        let policy = Policy::from_yaml(YAML).expect("yaml");
+
+        let name = |model, route| policy.rule_for(model, route).map(|r| r.name.as_str());
+        assert_eq!(
+            name("Hermes-3-Llama-3.1-70B", Some("/support/chat")),
+            Some("support")
+        );
+        assert_eq!(
+            name("Hermes-3-Llama-3.1-70B", Some("/sales/chat")),
+            Some("default")
+        );
+        assert_eq!(name("Hermes-3-Llama-3.1-70B", None), Some("default"));
+        assert_eq!(
+            name("neural-chat-7b-v3-3", Some("/support/chat")),
+            Some("default")
+        );
+
+        let cases = [
+            ("*", "", true),
+            ("abc", "abc", true),
+            ("abc", "abcd", false),
+            ("a*c", "abbbc", true),
+            ("a*c", "abbbd", false),
+            ("*-7b-*", "neural-chat-7b-v3-3", true),
+            ("a*b*a", "aba", true),
+            ("ab*ba", "aba", false),
+        ];
+
+        for (pattern, text, expected) in cases {
+            assert_eq!(wildcard(pattern, text), expected, "{} {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn redact_parts() {
+        let mut content = chat::MessageContent::Parts(vec![
+            chat::Content::image_url("data:image/png;base64,iVBORw0KGgo=".to_string()),
+            chat::Content::text("My email is".to_string()),
+            chat::Content::text("jane@example.com".to_string()),
+        ]);
+
+        redact(&mut content, "My email is [EMAIL]");
+
+        assert_eq!(content.image_count(), 1);
+        assert_eq!(content.text(), "My email is [EMAIL]");
+        assert!(content.parts()[0].is_image());
+    }
+
+    #[test]
+    fn hot_reload() {
+        tokio_test::block_on(async {
+            let path = std::env::temp_dir().join(format!("pg-policy-{}.yaml", std::process::id()));
+            std::fs::write(&path, YAML).expect("write policy");
+
+            let store = PolicyStore::open(&path).await.expect("store");
+            assert_eq!(store.policy().rules.len(), 2);
+            assert!(!store.reload().await.expect("unchanged"));
+
+            let updated =
+                "version: 1\nrules:\n  - name: only\n    toxicity:\n      threshold: 0.25\n";
+            std::fs::write(&path, updated).expect("write policy");
+            assert!(store.reload().await.expect("reload"));
+            assert_eq!(store.policy().rules[0].name, "only");
+
+            std::fs::write(&path, "version: 1\nrules: [").expect("write policy");
+            let err = store.reload().await.expect_err("invalid policy");
+            std::fs::remove_file(&path).expect("remove policy");
+
+            assert!(
+                matches!(err, PgError::Policy { path: Some(_), .. }),
+                "{:?}",
+                err
+            );
+            assert_eq!(store.policy().rules[0].name, "only");
+        });
+    }
+}
@@ -0,0 +1,949 @@
+//! Reversible PII pseudonymization for chat conversations.
+//!
+//! The PII endpoint only returns the prompt with the PII replaced, so the answer of the
+//! model can not be mapped back to the real names and numbers. A [`Vault`] compares the
+//! original and the replaced prompt to find each piece of PII, gives it a placeholder
+//! that is unique within the conversation and records the mapping locally. The
+//! placeholders in the answer are then replaced with the originals again, also in
+//! streamed responses where a placeholder can be split across events.
+//!
+//! The vault holds the PII in memory only, it is never sent to the API.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{chat, client, pii, pseudonymize::Pseudonymizer};
+//!
+//! let clt = client::Client::new()?;
+//! let mut pseudo = Pseudonymizer::new(clt, pii::ReplaceMethod::Category);
+//!
+//! let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+//!     .add_message(
+//!         chat::Roles::User,
+//!         "Write a short thank you note to John Doe at john@example.com".to_string(),
+//!     );
+//!
+//! // The model receives "... to [PERSON_1] at [EMAIL_ADDRESS_1]" and the answer
+//! // contains the real name and email again.
+//! let resp = pseudo.generate_chat_completion(&req).await?;
+//! ```
+use std::{collections::HashMap, fmt, ops::Range, sync::Arc};
+
+use futures::{
+    future,
+    stream::{self, BoxStream, StreamExt},
+};
+
+use crate::{chat, client::Client, pii, Result};
+
+/// The number of words that are compared to find where two prompts match again.
+const MAX_SKIP: usize = 64;
+
+/// The label of placeholders when the category of the PII is not known.
+const DEFAULT_LABEL: &str = "PII";
+
+/// Holds the placeholders of a conversation and the PII they replace.
+///
+/// `Debug` only prints the number of entries so the PII does not end up in logs.
+#[derive(Clone, Default)]
+pub struct Vault {
+    /// Placeholder and original, in the order they were recorded.
+    entries: Vec<(String, String)>,
+    by_original: HashMap<String, usize>,
+    by_placeholder: HashMap<String, usize>,
+    counters: HashMap<String, usize>,
+}
+
+impl fmt::Debug for Vault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vault")
+            .field("entries", &self.entries.len())
+            .finish()
+    }
+}
+
+impl Vault {
+    /// Creates an empty vault.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of recorded placeholders.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no placeholders are recorded.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all placeholders.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the original text of a placeholder.
+    pub fn original(&self, placeholder: &str) -> Option<&str> {
+        self.by_placeholder
+            .get(placeholder)
+            .map(|&i| self.entries[i].1.as_str())
+    }
+
+    /// Returns the placeholder of an original text.
+    pub fn placeholder(&self, original: &str) -> Option<&str> {
+        self.by_original
+            .get(original)
+            .map(|&i| self.entries[i].0.as_str())
+    }
+
+    /// Records the PII replaced in a text and returns the text with the placeholders of
+    /// the vault. The PII is found by comparing the original text with the text
+    /// returned from the PII endpoint, or any other detector that replaces PII in place.
+    ///
+    /// For [`pii::ReplaceMethod::Category`] and [`pii::ReplaceMethod::Mask`] the
+    /// placeholders are numbered per category, e.g. `[EMAIL_ADDRESS_1]`. For
+    /// [`pii::ReplaceMethod::Fake`] and [`pii::ReplaceMethod::Random`] the replacement
+    /// is used as the placeholder unless it is already used for other PII, and it is
+    /// only restored where it is a whole word, so `Maria` is restored but `Mariana` is
+    /// not. PII that is already in the vault keeps its placeholder.
+    ///
+    /// ## Arguments
+    ///
+    /// * `original` - The text with the PII.
+    /// * `replaced` - The text with the PII replaced.
+    /// * `method` - The method used to replace the PII.
+    pub fn record(&mut self, original: &str, replaced: &str, method: pii::ReplaceMethod) -> String {
+        let mut out = String::with_capacity(original.len());
+        let mut last = 0;
+
+        for (orig, repl) in replaced_spans(original, replaced) {
+            let pii = &original[orig.clone()];
+            let placeholder = self.insert(pii, &replaced[repl], method, original);
+
+            out.push_str(&original[last..orig.start]);
+            out.push_str(&placeholder);
+            last = orig.end;
+        }
+
+        out.push_str(&original[last..]);
+        out
+    }
+
+    /// Replaces the PII already in the vault with its placeholders, used for text that
+    /// was restored before, e.g. earlier answers of the model.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text with the PII.
+    pub fn substitute(&self, text: &str) -> String {
+        let pairs: Vec<(String, String)> = self
+            .entries
+            .iter()
+            .map(|(placeholder, original)| (original.clone(), placeholder.clone()))
+            .collect();
+
+        replace_all(text, &sorted(pairs))
+    }
+
+    /// Replaces the placeholders in a text with the original PII.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text with placeholders, usually the answer of the model.
+    pub fn restore(&self, text: &str) -> String {
+        replace_all(text, &sorted(self.entries.clone()))
+    }
+
+    /// Returns a restorer for text that is received in parts.
+    pub fn restorer(&self) -> Restorer {
+        Restorer {
+            pairs: Arc::new(sorted(self.entries.clone())),
+            pending: String::new(),
+            before: None,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        original: &str,
+        replacement: &str,
+        method: pii::ReplaceMethod,
+        text: &str,
+    ) -> String {
+        if let Some(placeholder) = self.placeholder(original) {
+            return placeholder.to_string();
+        }
+
+        let reuse = matches!(
+            method,
+            pii::ReplaceMethod::Fake | pii::ReplaceMethod::Random
+        ) && !replacement.trim().is_empty()
+            && !self.by_placeholder.contains_key(replacement)
+            && !self.by_original.contains_key(replacement)
+            && !text.contains(replacement);
+
+        let placeholder = if reuse {
+            replacement.to_string()
+        } else {
+            let label = match method {
+                pii::ReplaceMethod::Category => category_label(replacement),
+                _ => DEFAULT_LABEL.to_string(),
+            };
+            self.next_placeholder(label)
+        };
+
+        let i = self.entries.len();
+        self.entries
+            .push((placeholder.clone(), original.to_string()));
+        self.by_original.insert(original.to_string(), i);
+        self.by_placeholder.insert(placeholder.clone(), i);
+
+        placeholder
+    }
+
+    fn next_placeholder(&mut self, label: String) -> String {
+        loop {
+            let n = self.counters.entry(label.clone()).or_insert(0);
+            *n += 1;
+
+            let placeholder = format!("[{}_{}]", label, n);
+            if !self.by_placeholder.contains_key(&placeholder) {
+                return placeholder;
+            }
+        }
+    }
+}
+
+/// Replaces placeholders in text that is received in parts, e.g. the deltas of a
+/// streamed response. Text that could be the start of a placeholder is held back until
+/// the next part shows whether it is one.
+#[derive(Debug, Clone)]
+pub struct Restorer {
+    pairs: Arc<Vec<(String, String)>>,
+    pending: String,
+    before: Option<char>,
+}
+
+impl Restorer {
+    /// Adds a part of the text and returns the restored text that is complete.
+    ///
+    /// ## Arguments
+    ///
+    /// * `delta` - The next part of the text.
+    pub fn push(&mut self, delta: &str) -> String {
+        self.pending.push_str(delta);
+
+        let cut = self.pending.len() - self.held_back();
+        let out = replace_after(&self.pending[..cut], &self.pairs, self.before);
+        if let Some(c) = self.pending[..cut].chars().next_back() {
+            self.before = Some(c);
+        }
+        self.pending.drain(..cut);
+
+        out
+    }
+
+    /// Returns the rest of the restored text, call it when the text is complete.
+    pub fn finish(&mut self) -> String {
+        let out = replace_after(&self.pending, &self.pairs, self.before);
+        if let Some(c) = self.pending.chars().next_back() {
+            self.before = Some(c);
+        }
+        self.pending.clear();
+
+        out
+    }
+
+    /// Returns the length of the longest end of the pending text that is the start of
+    /// a placeholder, or a whole placeholder that the next part could continue as a
+    /// word.
+    fn held_back(&self) -> usize {
+        self.pairs
+            .iter()
+            .filter_map(|(placeholder, _)| {
+                let word_end = placeholder
+                    .chars()
+                    .next_back()
+                    .is_some_and(char::is_alphanumeric);
+
+                placeholder
+                    .char_indices()
+                    .skip(1)
+                    .map(|(i, _)| i)
+                    .chain(word_end.then_some(placeholder.len()))
+                    .filter(|&i| self.pending.ends_with(&placeholder[..i]))
+                    .max()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// The state of a stream of events with the PII restored.
+struct Restoring {
+    restorer: Restorer,
+    stream: BoxStream<'static, Result<chat::ResponseEvents>>,
+    /// The restorer for each choice index.
+    restorers: HashMap<i64, Restorer>,
+    /// The last event, used for the event that sends the rest of the text.
+    last: Option<chat::ResponseEvents>,
+    done: bool,
+}
+
+impl Restoring {
+    fn restore_event(&mut self, mut event: chat::ResponseEvents) -> chat::ResponseEvents {
+        for choice in &mut event.choices {
+            let r = self
+                .restorers
+                .entry(choice.index)
+                .or_insert_with(|| self.restorer.clone());
+
+            let mut text = r.push(&choice.delta.content);
+            if choice.finish_reason.is_some() {
+                text.push_str(&r.finish());
+            }
+
+            choice.delta.content = text;
+        }
+
+        self.last = Some(event.clone());
+        event
+    }
+
+    /// Returns an event with the text still held back when the stream ended.
+    fn flush(&mut self) -> Option<chat::ResponseEvents> {
+        let mut pending: Vec<(i64, String)> = self
+            .restorers
+            .iter_mut()
+            .map(|(index, r)| (*index, r.finish()))
+            .filter(|(_, text)| !text.is_empty())
+            .collect();
+
+        if pending.is_empty() {
+            return None;
+        }
+
+        pending.sort_by_key(|(index, _)| *index);
+
+        let mut event = self.last.take().unwrap_or_default();
+        event.choices = pending
+            .into_iter()
+            .map(|(index, content)| chat::ChoiceEvents {
+                index,
+                delta: chat::EventsDelta {
+                    content,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        Some(event)
+    }
+}
+
+/// Sends chat requests with the PII of the user replaced by placeholders and restores
+/// the PII in the responses. Use one pseudonymizer per conversation.
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    client: Client,
+    method: pii::ReplaceMethod,
+    vault: Vault,
+    /// Texts that were already checked, with the placeholders in place.
+    checked: HashMap<String, String>,
+}
+
+impl fmt::Debug for Pseudonymizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pseudonymizer")
+            .field("method", &self.method)
+            .field("vault", &self.vault)
+            .finish()
+    }
+}
+
+impl Pseudonymizer {
+    /// Creates a pseudonymizer with an empty vault.
+    ///
+    /// ## Arguments
+    ///
+    /// * `client` - The client used to call the PII and chat endpoints.
+    /// * `method` - The method the PII endpoint uses to replace the PII.
+    pub fn new(client: Client, method: pii::ReplaceMethod) -> Self {
+        Self {
+            client,
+            method,
+            vault: Vault::new(),
+            checked: HashMap::new(),
+        }
+    }
+
+    /// Returns the vault of the conversation.
+    pub fn vault(&self) -> &Vault {
+        &self.vault
+    }
+
+    /// Replaces the PII in a text with placeholders. Texts that were checked before are
+    /// not sent to the PII endpoint again.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text with the PII.
+    ///
+    /// Returns an error if the PII endpoint fails.
+    pub async fn pseudonymize(&mut self, text: &str) -> Result<String> {
+        if text.is_empty() {
+            return Ok(String::new());
+        }
+
+        self.check(vec![text.to_string()]).await?;
+
+        Ok(self.checked[text].clone())
+    }
+
+    /// Replaces the placeholders in a text with the original PII.
+    ///
+    /// ## Arguments
+    ///
+    /// * `text` - The text with placeholders.
+    pub fn restore(&self, text: &str) -> String {
+        self.vault.restore(text)
+    }
+
+    /// Calls the chat completion endpoint with the PII replaced. The system and user
+    /// messages are checked with the PII endpoint, the PII of the vault is replaced in
+    /// the other messages. The placeholders in the choices of the response are replaced
+    /// with the original PII.
+    ///
+    /// ## Arguments
+    ///
+    /// * `req` - An instance of [`chat::Request`].
+    ///
+    /// Returns an error if the PII or chat endpoint fails.
+    pub async fn generate_chat_completion(
+        &mut self,
+        req: &chat::Request<chat::Message>,
+    ) -> Result<chat::Response> {
+        let req = self.pseudonymize_request(req).await?;
+
+        let mut resp = self.client.generate_chat_completion(&req).await?;
+
+        for choice in &mut resp.choices {
+            restore_content(&mut choice.message.content, &self.vault);
+        }
+
+        Ok(resp)
+    }
+
+    /// Calls the chat completion endpoint with the PII replaced and returns the stream
+    /// of events with the PII restored, see [`Pseudonymizer::generate_chat_completion`].
+    /// Text that could be the start of a placeholder is delayed until the next event of
+    /// the choice, and sent with the event that has the finish reason, or with a last
+    /// event if the stream ends without one.
+    ///
+    /// ## Arguments
+    ///
+    /// * `req` - An instance of [`chat::Request`].
+    ///
+    /// Returns an error if the PII or chat endpoint fails.
+    pub async fn generate_chat_completion_stream(
+        &mut self,
+        req: &chat::Request<chat::Message>,
+    ) -> Result<BoxStream<'static, Result<chat::ResponseEvents>>> {
+        let req = self.pseudonymize_request(req).await?;
+
+        let stream = self.client.generate_chat_completion_stream(&req).await?;
+
+        let state = Restoring {
+            restorer: self.vault.restorer(),
+            stream,
+            restorers: HashMap::new(),
+            last: None,
+            done: false,
+        };
+
+        Ok(stream::unfold(state, |mut state| async move {
+            if state.done {
+                return None;
+            }
+
+            match state.stream.next().await {
+                Some(Ok(event)) => Some((Ok(state.restore_event(event)), state)),
+                Some(Err(err)) => {
+                    state.done = true;
+                    Some((Err(err), state))
+                }
+                None => {
+                    state.done = true;
+                    state.flush().map(|event| (Ok(event), state))
+                }
+            }
+        })
+        .boxed())
+    }
+
+    async fn pseudonymize_request(
+        &mut self,
+        req: &chat::Request<chat::Message>,
+    ) -> Result<chat::Request<chat::Message>> {
+        let checked = |m: &chat::Message| matches!(m.role, chat::Roles::System | chat::Roles::User);
+
+        let texts = req
+            .messages
+            .iter()
+            .filter(|m| checked(m))
+            .flat_map(|m| texts(&m.content))
+            .collect();
+
+        self.check(texts).await?;
+
+        let mut req = req.clone();
+
+        for msg in &mut req.messages {
+            if checked(msg) {
+                map_texts(&mut msg.content, |t| self.checked[t].clone());
+            } else {
+                map_texts(&mut msg.content, |t| self.vault.substitute(t));
+            }
+        }
+
+        Ok(req)
+    }
+
+    /// Sends the texts that were not checked before to the PII endpoint concurrently and
+    /// records the PII in the vault.
+    async fn check(&mut self, texts: Vec<String>) -> Result<()> {
+        let mut new: Vec<String> = Vec::new();
+        for text in texts {
+            if !text.is_empty() && !self.checked.contains_key(&text) && !new.contains(&text) {
+                new.push(text);
+            }
+        }
+
+        let client = &self.client;
+        let method = self.method;
+
+        let replaced = future::try_join_all(new.iter().map(|text| async move {
+            let req = pii::Request::new(text.clone(), true, method);
+            let resp = client.pii(&req).await?;
+
+            Ok::<_, crate::PgError>(
+                resp.checks
+                    .into_iter()
+                    .next()
+                    .map(|c| c.new_prompt)
+                    .unwrap_or_else(|| text.clone()),
+            )
+        }))
+        .await?;
+
+        for (text, replaced) in new.into_iter().zip(replaced) {
+            let pseudonymized = self.vault.record(&text, &replaced, self.method);
+            self.checked.insert(text, pseudonymized);
+        }
+
+        Ok(())
+    }
+}
+
+fn texts(content: &chat::MessageContent) -> Vec<String> {
+    match content {
+        chat::MessageContent::Text(s) => vec![s.clone()],
+        chat::MessageContent::Parts(parts) => parts.iter().filter_map(|p| p.text.clone()).collect(),
+    }
+}
+
+fn map_texts<F: FnMut(&str) -> String>(content: &mut chat::MessageContent, mut f: F) {
+    match content {
+        chat::MessageContent::Text(s) => {
+            if !s.is_empty() {
+                *s = f(s);
+            }
+        }
+        chat::MessageContent::Parts(parts) => {
+            for text in parts.iter_mut().filter_map(|p| p.text.as_mut()) {
+                if !text.is_empty() {
+                    *text = f(text);
+                }
+            }
+        }
+    }
+}
+
+fn restore_content(content: &mut chat::MessageContent, vault: &Vault) {
+    map_texts(content, |t| vault.restore(t));
+}
+
+/// Returns the label of a category replacement, e.g. `EMAIL_ADDRESS` for
+/// `[EMAIL_ADDRESS]` or `<EMAIL_ADDRESS>`.
+fn category_label(replacement: &str) -> String {
+    let label = replacement
+        .trim()
+        .trim_matches(|c| matches!(c, '[' | ']' | '<' | '>' | '{' | '}'))
+        .trim()
+        .replace([' ', '-'], "_")
+        .to_ascii_uppercase();
+
+    if !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        label
+    } else {
+        DEFAULT_LABEL.to_string()
+    }
+}
+
+/// Sorts the pairs by the length of the text to replace, longest first, so that a
+/// text that is the start of another one does not replace it partially.
+fn sorted(mut pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+    pairs.retain(|(from, _)| !from.is_empty());
+    pairs.sort_by_key(|(from, _)| std::cmp::Reverse(from.len()));
+    pairs
+}
+
+/// Replaces every occurrence of the first text of the pairs with the second in a
+/// single pass, so replaced text is not replaced again.
+fn replace_all(text: &str, pairs: &[(String, String)]) -> String {
+    replace_after(text, pairs, None)
+}
+
+/// Replaces like [`replace_all`] in a text that follows the character `before`.
+/// Texts that start or end with a word character are only replaced at word
+/// boundaries, so a fake name like `Maria` is not replaced inside `Mariana`. The end
+/// of the text counts as a boundary.
+fn replace_after(text: &str, pairs: &[(String, String)], before: Option<char>) -> String {
+    if pairs.is_empty() {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut before = before;
+
+    while let Some(c) = rest.chars().next() {
+        match pairs.iter().find(|(from, _)| {
+            rest.starts_with(from.as_str())
+                && at_boundary(from, before, rest[from.len()..].chars().next())
+        }) {
+            Some((from, to)) => {
+                out.push_str(to);
+                rest = &rest[from.len()..];
+                before = from.chars().next_back();
+            }
+            None => {
+                out.push(c);
+                rest = &rest[c.len_utf8()..];
+                before = Some(c);
+            }
+        }
+    }
+
+    out
+}
+
+/// Returns true if `from` between the characters `before` and `after` does not
+/// continue a word on either side.
+fn at_boundary(from: &str, before: Option<char>, after: Option<char>) -> bool {
+    let word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+
+    let joins_before = word(from.chars().next()) && word(before);
+    let joins_after = word(from.chars().next_back()) && word(after);
+
+    !joins_before && !joins_after
+}
+
+/// Returns the byte ranges of the words of a text.
+fn words(s: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (i, c) in s.char_indices() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(st)) => {
+                words.push(st..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(st) = start {
+        words.push(st..s.len());
+    }
+
+    words
+}
+
+/// Finds the spans of the original text that were replaced, with the text that
+/// replaced them. The words of both texts are compared, after a difference the
+/// closest position where the texts match again is used.
+fn replaced_spans(original: &str, replaced: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let a = words(original);
+    let b = words(replaced);
+
+    let eq = |i: usize, j: usize| original[a[i].clone()] == replaced[b[j].clone()];
+
+    // The texts match again at (x, y) if the next words are equal or both texts end.
+    let anchored = |x: usize, y: usize| {
+        (x == a.len() && y == b.len()) || (x < a.len() && y < b.len() && eq(x, y))
+    };
+
+    let mut spans = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && eq(i, j) {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (x, y) = (1..=2 * MAX_SKIP)
+            .flat_map(|d| (0..=d).map(move |dx| (i + dx, j + d - dx)))
+            .find(|&(x, y)| x <= a.len() && y <= b.len() && anchored(x, y))
+            .unwrap_or((a.len(), b.len()));
+
+        let orig = span(&a, i, x, original.len());
+        let repl = span(&b, j, y, replaced.len());
+
+        if let Some(pair) = trim_span(original, orig, replaced, repl) {
+            spans.push(pair);
+        }
+
+        i = x;
+        j = y;
+    }
+
+    spans
+}
+
+/// Returns the byte range of the words from `start` to `end`, an empty range at the
+/// next word if there are none.
+fn span(words: &[Range<usize>], start: usize, end: usize, len: usize) -> Range<usize> {
+    if end > start {
+        words[start].start..words[end - 1].end
+    } else {
+        let at = words.get(start).map(|w| w.start).unwrap_or(len);
+        at..at
+    }
+}
+
+/// Removes the punctuation both spans start or end with, e.g. the period after an
+/// email address at the end of a sentence.
+fn trim_span(
+    original: &str,
+    mut orig: Range<usize>,
+    replaced: &str,
+    mut repl: Range<usize>,
+) -> Option<(Range<usize>, Range<usize>)> {
+    let trimmable = |c: char| !c.is_alphanumeric();
+
+    loop {
+        let a = original[orig.clone()].chars().next();
+        let b = replaced[repl.clone()].chars().next();
+        match (a, b) {
+            (Some(a), Some(b)) if a == b && trimmable(a) => {
+                orig.start += a.len_utf8();
+                repl.start += b.len_utf8();
+            }
+            _ => break,
+        }
+    }
+
+    loop {
+        let a = original[orig.clone()].chars().next_back();
+        let b = replaced[repl.clone()].chars().next_back();
+        match (a, b) {
+            (Some(a), Some(b)) if a == b && trimmable(a) => {
+                orig.end -= a.len_utf8();
+                repl.end -= b.len_utf8();
+            }
+            _ => break,
+        }
+    }
+
+    if original[orig.clone()].trim().is_empty() {
+        return None;
+    }
+
+    Some((orig, repl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_category() {
+        let mut vault = Vault::new();
+
+        let text = vault.record(
+            "Email John Doe at john@example.com, or Jane at jane@example.com.",
+            "Email [PERSON] at [EMAIL_ADDRESS], or [PERSON] at [EMAIL_ADDRESS].",
+            pii::ReplaceMethod::Category,
+        );
+
+        assert_eq!(
+            text,
+            "Email [PERSON_1] at [EMAIL_ADDRESS_1], or [PERSON_2] at [EMAIL_ADDRESS_2]."
+        );
+        assert_eq!(vault.len(), 4);
+        assert_eq!(vault.original("[PERSON_1]"), Some("John Doe"));
+        assert_eq!(
+            vault.original("[EMAIL_ADDRESS_2]"),
+            Some("jane@example.com")
+        );
+
+        let text = vault.record(
+            "Is john@example.com still valid? My SSN is 123-45-6789",
+            "Is <EMAIL_ADDRESS> still valid? My SSN is <US_SSN>",
+            pii::ReplaceMethod::Category,
+        );
+
+        assert_eq!(
+            text,
+            "Is [EMAIL_ADDRESS_1] still valid? My SSN is [US_SSN_1]"
+        );
+
+        assert_eq!(
+            vault.restore("Sent to [PERSON_1] ([EMAIL_ADDRESS_1]) and [PERSON_2]."),
+            "Sent to John Doe (john@example.com) and Jane."
+        );
+        assert_eq!(
+            vault.substitute("John Doe wrote to jane@example.com"),
+            "[PERSON_1] wrote to [EMAIL_ADDRESS_2]"
+        );
+    }
+
+    #[test]
+    fn record_fake() {
+        let mut vault = Vault::new();
+
+        let text = vault.record(
+            "My email is jane@example.com and my name is Jane",
+            "My email is oyo@yukmt.fjw and my name is Maria",
+            pii::ReplaceMethod::Fake,
+        );
+
+        assert_eq!(text, "My email is oyo@yukmt.fjw and my name is Maria");
+        assert_eq!(
+            vault.restore("Hi Maria, I wrote to oyo@yukmt.fjw"),
+            "Hi Jane, I wrote to jane@example.com"
+        );
+
+        // The fake value is already used, a numbered placeholder is used instead.
+        let text = vault.record("Call Bob", "Call Maria", pii::ReplaceMethod::Fake);
+        assert_eq!(text, "Call [PII_1]");
+
+        let text = vault.record(
+            "Mask 4111 1111 1111 1111 now",
+            "Mask **** now",
+            pii::ReplaceMethod::Mask,
+        );
+        assert_eq!(text, "Mask [PII_2] now");
+        assert_eq!(vault.original("[PII_2]"), Some("4111 1111 1111 1111"));
+
+        assert_eq!(format!("{:?}", vault), "Vault { entries: 4 }");
+    }
+
+    #[test]
+    fn fake_inside_word() {
+        let mut vault = Vault::new();
+        vault.record(
+            "My name is Jane",
+            "My name is Maria",
+            pii::ReplaceMethod::Fake,
+        );
+
+        let answer = "Mariana met Maria, not AnnaMaria.";
+        let expected = "Mariana met Jane, not AnnaMaria.";
+        assert_eq!(vault.restore(answer), expected);
+        assert_eq!(vault.substitute("Janet and Jane"), "Janet and Maria");
+
+        for size in 1..=answer.len() {
+            let mut restorer = vault.restorer();
+            let mut out = String::new();
+
+            for delta in answer.as_bytes().chunks(size) {
+                out.push_str(&restorer.push(std::str::from_utf8(delta).unwrap()));
+            }
+            out.push_str(&restorer.finish());
+
+            assert_eq!(out, expected, "delta size {}", size);
+        }
+
+        let mut restorer = vault.restorer();
+        assert_eq!(restorer.push("Hi Maria"), "Hi ");
+        assert_eq!(restorer.push("na"), "Mariana");
+        assert_eq!(restorer.push(" and Maria"), " and ");
+        assert_eq!(restorer.finish(), "Jane");
+    }
+
+    #[test]
+    fn pseudonymize_empty() {
+        let pg_env = crate::client::PgEnvironment {
+            key: "api-key".to_string(),
+            host: "http://127.0.0.1:1".to_string(),
+        };
+
+        let clt = Client::from_environment(pg_env).expect("client value");
+        let mut pseudo = Pseudonymizer::new(clt, pii::ReplaceMethod::Category);
+
+        let text = tokio_test::block_on(pseudo.pseudonymize("")).expect("empty text");
+        assert_eq!(text, "");
+        assert!(pseudo.vault().is_empty());
+    }
+
+    #[test]
+    fn spans_without_pii() {
+        assert!(replaced_spans("nothing to see here", "nothing to see here").is_empty());
+        assert!(replaced_spans("", "").is_empty());
+
+        let mut vault = Vault::new();
+        assert_eq!(
+            vault.record(
+                "  spacing\tis kept  ",
+                "  spacing\tis kept  ",
+                pii::ReplaceMethod::Category
+            ),
+            "  spacing\tis kept  "
+        );
+        assert!(vault.is_empty());
+
+        let text = vault.record("John", "[PERSON]", pii::ReplaceMethod::Category);
+        assert_eq!(text, "[PERSON_1]");
+    }
+
+    #[test]
+    fn stream_restore() {
+        let mut vault = Vault::new();
+        vault.record(
+            "Email John Doe at john@example.com",
+            "Email [PERSON] at [EMAIL_ADDRESS]",
+            pii::ReplaceMethod::Category,
+        );
+
+        let answer = "Dear [PERSON_1], we will write to [EMAIL_ADDRESS_1] soon. [PERSON";
+        let expected = "Dear John Doe, we will write to john@example.com soon. [PERSON";
+
+        for size in 1..=answer.len() {
+            let mut restorer = vault.restorer();
+            let mut out = String::new();
+
+            let mut rest = answer;
+            while !rest.is_empty() {
+                let (delta, tail) = rest.split_at(size.min(rest.len()));
+                out.push_str(&restorer.push(delta));
+                rest = tail;
+            }
+            out.push_str(&restorer.finish());
+
+            assert_eq!(out, expected, "delta size {}", size);
+        }
+
+        let mut restorer = vault.restorer();
+        assert_eq!(restorer.push("Hello [PERS"), "Hello ");
+        assert_eq!(restorer.push("ON_1]!"), "John Doe!");
+        assert_eq!(restorer.push("[x]"), "[x]");
+    }
+}
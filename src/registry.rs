@@ -0,0 +1,268 @@
+//! Cache of the models list, used by the client to validate requests against the
+//! capabilities and context length of the model before they are sent.
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+use crate::{models, tokenize, PgError, Result};
+
+/// The default time the models list is cached.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+pub(crate) type Models = Arc<HashMap<String, models::ModelData>>;
+
+/// Holds the models list until the TTL expires.
+#[derive(Debug)]
+pub(crate) struct Registry {
+    ttl: Duration,
+    cache: Mutex<Option<(Instant, Models)>>,
+}
+
+impl Registry {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Returns the cached models, loading them if the cache is empty or expired. The
+    /// lock is held while loading so concurrent callers share a single request.
+    pub async fn models<F, Fut>(&self, load: F) -> Result<Models>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<models::Response>>,
+    {
+        let mut cache = self.cache.lock().await;
+
+        if let Some((loaded, models)) = cache.as_ref() {
+            if loaded.elapsed() < self.ttl {
+                return Ok(models.clone());
+            }
+        }
+
+        let models: Models = Arc::new(
+            load()
+                .await?
+                .data
+                .into_iter()
+                .map(|m| (m.id.clone(), m))
+                .collect(),
+        );
+
+        *cache = Some((Instant::now(), models.clone()));
+
+        Ok(models)
+    }
+
+    /// Removes the cached models, they are loaded again on the next call.
+    pub async fn clear(&self) {
+        *self.cache.lock().await = None;
+    }
+}
+
+/// What a request needs from the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Check {
+    pub requirement: models::Capability,
+    /// The estimated number of input tokens, 0 to skip the context length check.
+    pub input_tokens: usize,
+    pub max_tokens: Option<i64>,
+}
+
+impl Check {
+    pub fn new(requirement: models::Capability) -> Self {
+        Self {
+            requirement,
+            input_tokens: 0,
+            max_tokens: None,
+        }
+    }
+
+    /// Returns the check for a completion request.
+    pub fn completion(prompt: &str, max_tokens: Option<i64>) -> Self {
+        Self {
+            requirement: models::Capability::Completion,
+            input_tokens: tokenize::estimate(prompt),
+            max_tokens,
+        }
+    }
+
+    /// Returns the check for an embedding request.
+    pub fn embedding(images: bool) -> Self {
+        let requirement = if images {
+            models::Capability::EmbeddingWithImage
+        } else {
+            models::Capability::Embedding
+        };
+
+        Self::new(requirement)
+    }
+
+    /// Returns the check for the serialized messages of a chat request. Messages with
+    /// image parts require a model that supports images.
+    pub fn chat(messages: &serde_json::Value, max_tokens: i64) -> Self {
+        let mut chars = 0;
+        let mut images = false;
+        let mut count = 0;
+
+        for msg in messages.as_array().into_iter().flatten() {
+            count += 1;
+
+            match &msg["content"] {
+                serde_json::Value::String(s) => chars += s.chars().count(),
+                serde_json::Value::Array(parts) => {
+                    for part in parts {
+                        if let Some(text) = part["text"].as_str() {
+                            chars += text.chars().count();
+                        }
+                        images |= !part["image_url"].is_null();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let requirement = if images {
+            models::Capability::ChatWithImage
+        } else {
+            models::Capability::ChatCompletion
+        };
+
+        Self {
+            requirement,
+            input_tokens: tokenize::estimate_chars(chars) + count * tokenize::MESSAGE_OVERHEAD,
+            max_tokens: Some(max_tokens),
+        }
+    }
+
+    /// Returns an error if the model does not support the request.
+    pub fn validate(&self, model: &models::ModelData) -> Result<()> {
+        if !model.capabilities.supports(&self.requirement) {
+            return Err(PgError::Validation(format!(
+                "model {} does not support {}",
+                model.id, self.requirement
+            )));
+        }
+
+        let max_tokens = self.max_tokens.unwrap_or(0).max(0) as usize;
+        let max_context = model.max_context_length.max(0) as usize;
+
+        if self.input_tokens > 0 && max_context > 0 && self.input_tokens + max_tokens > max_context
+        {
+            return Err(PgError::Validation(format!(
+                "about {} input tokens plus {} max tokens exceed the context length of {} tokens of model {}",
+                self.input_tokens, max_tokens, max_context, model.id
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(caps: models::ModelCapabilities) -> models::ModelData {
+        models::ModelData {
+            id: "test-model".to_string(),
+            max_context_length: 100,
+            capabilities: caps,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn chat_checks() {
+        let text = serde_json::json!([
+            {"role": "system", "content": "abcd"},
+            {"role": "user", "content": [{"type": "text", "text": "abcdefgh"}]}
+        ]);
+
+        let check = Check::chat(&text, 50);
+        assert_eq!(check.requirement, models::Capability::ChatCompletion);
+        assert_eq!(check.input_tokens, 3 + 2 * tokenize::MESSAGE_OVERHEAD);
+
+        let vision = serde_json::json!([
+            {"role": "user", "content": [
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+            ]}
+        ]);
+
+        assert_eq!(
+            Check::chat(&vision, 50).requirement,
+            models::Capability::ChatWithImage
+        );
+    }
+
+    #[test]
+    fn validation() {
+        let chat_model = model(models::ModelCapabilities {
+            chat_completion: true,
+            ..Default::default()
+        });
+
+        let check = Check {
+            requirement: models::Capability::ChatCompletion,
+            input_tokens: 40,
+            max_tokens: Some(60),
+        };
+        assert!(check.validate(&chat_model).is_ok());
+
+        let err = Check {
+            max_tokens: Some(61),
+            ..check.clone()
+        }
+        .validate(&chat_model)
+        .expect_err("context length");
+        assert!(err.to_string().contains("context length of 100"), "{}", err);
+
+        let err = Check::new(models::Capability::ChatWithImage)
+            .validate(&chat_model)
+            .expect_err("vision");
+        assert_eq!(
+            err.to_string(),
+            "invalid request: model test-model does not support chat-with-image"
+        );
+
+        assert!(Check::new(models::Capability::Embedding)
+            .validate(&chat_model)
+            .is_err());
+    }
+
+    #[test]
+    fn cache_ttl() {
+        tokio_test::block_on(async {
+            let loads = std::sync::atomic::AtomicUsize::new(0);
+            let load = || async {
+                loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Ok(models::Response {
+                    data: vec![model(Default::default())],
+                    ..Default::default()
+                })
+            };
+
+            let registry = Registry::new(Duration::from_secs(60));
+            registry.models(load).await.expect("models");
+            let models = registry.models(load).await.expect("cached models");
+            assert!(models.contains_key("test-model"));
+            assert_eq!(loads.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+            registry.clear().await;
+            registry.models(load).await.expect("models");
+            assert_eq!(loads.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+            let registry = Registry::new(Duration::ZERO);
+            registry.models(load).await.expect("models");
+            registry.models(load).await.expect("models");
+            assert_eq!(loads.load(std::sync::atomic::Ordering::SeqCst), 4);
+        });
+    }
+}
@@ -0,0 +1,274 @@
+//! Retry policy used by the client when a request fails with a transient error.
+use std::time::{Duration, SystemTime};
+
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+
+use crate::PgError;
+
+/// Controls how the client retries requests that fail with a transient error.
+///
+/// A request is retried when the API responds with one of the retryable status codes
+/// (by default 429, 500, 502, 503 and 504), when the request times out or when the
+/// connection to the API fails. The delay between attempts grows exponentially from
+/// `base_delay` up to `max_delay`. If the API sends a `Retry-After` header its value is
+/// used instead, capped at `max_delay`.
+///
+/// # Example
+///
+/// ```ignore
+/// use std::time::Duration;
+/// use prediction_guard::{client, retry::RetryPolicy};
+///
+/// let policy = RetryPolicy::default()
+///     .max_attempts(5)
+///     .base_delay(Duration::from_millis(250));
+///
+/// let clt = client::Client::new()?.with_retry_policy(policy);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: bool,
+    statuses: Vec<StatusCode>,
+    retry_timeouts: bool,
+    retry_connect: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: true,
+            statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_timeouts: true,
+            retry_connect: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy that never retries. This is the policy used by the client
+    /// unless another one is set.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the maximum number of attempts, including the first one.
+    ///
+    /// ## Arguments
+    ///
+    /// * `attempts` - The maximum number of attempts. A value of 0 is treated as 1.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry. Each further retry doubles the delay.
+    ///
+    /// ## Arguments
+    ///
+    /// * `delay` - The delay before the first retry.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Sets the maximum delay between two attempts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `delay` - The maximum delay between attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Enables or disables random jitter on the delay between attempts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `jitter` - When true, each delay is a random value between zero and the computed delay.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the status codes that are retried.
+    ///
+    /// ## Arguments
+    ///
+    /// * `statuses` - The status codes that are considered transient.
+    pub fn retry_statuses(mut self, statuses: Vec<StatusCode>) -> Self {
+        self.statuses = statuses;
+        self
+    }
+
+    /// Sets whether requests that time out are retried.
+    ///
+    /// ## Arguments
+    ///
+    /// * `retry` - Determines whether to retry timed out requests.
+    pub fn retry_timeouts(mut self, retry: bool) -> Self {
+        self.retry_timeouts = retry;
+        self
+    }
+
+    /// Sets whether requests that fail to connect to the API are retried.
+    ///
+    /// ## Arguments
+    ///
+    /// * `retry` - Determines whether to retry connection failures.
+    pub fn retry_connect(mut self, retry: bool) -> Self {
+        self.retry_connect = retry;
+        self
+    }
+
+    /// Returns the maximum number of attempts, including the first one.
+    pub fn attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Returns true if a response with the status code should be retried.
+    pub(crate) fn retry_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    /// Returns true if the error returned from sending a request should be retried.
+    pub(crate) fn retry_error(&self, err: &PgError) -> bool {
+        match err {
+            PgError::Timeout(_) => self.retry_timeouts,
+            PgError::Transport(e) => self.retry_connect && e.is_connect(),
+            _ => false,
+        }
+    }
+
+    /// Computes the delay to wait before the next attempt.
+    ///
+    /// ## Arguments
+    ///
+    /// * `attempt` - The attempt that just failed, starting at 1.
+    /// * `retry_after` - The delay requested by the API with the `Retry-After` header.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(d) = retry_after {
+            return d.min(self.max_delay);
+        }
+
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .base_delay
+            .checked_mul(factor)
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            delay.mul_f64(fastrand::f64())
+        } else {
+            delay
+        }
+    }
+}
+
+/// Reads the `Retry-After` header, which is either a number of seconds or an HTTP date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn exponential_delay() {
+        let policy = RetryPolicy::default()
+            .base_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_millis(350))
+            .jitter(false);
+
+        assert_eq!(policy.delay(1, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(200));
+        assert_eq!(policy.delay(3, None), Duration::from_millis(350));
+        assert_eq!(policy.delay(40, None), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn jitter_delay() {
+        let policy = RetryPolicy::default()
+            .base_delay(Duration::from_millis(100))
+            .jitter(true);
+
+        for _ in 0..100 {
+            assert!(policy.delay(2, None) <= Duration::from_millis(200));
+        }
+    }
+
+    #[test]
+    fn retry_after_delay() {
+        let policy = RetryPolicy::default().max_delay(Duration::from_secs(10));
+
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(2))),
+            Duration::from_secs(2)
+        );
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(60))),
+            Duration::from_secs(10)
+        );
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+
+        headers.insert(
+            RETRY_AFTER,
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+    }
+
+    #[test]
+    fn none_policy() {
+        let policy = RetryPolicy::none();
+        assert_eq!(policy.attempts(), 1);
+
+        let policy = RetryPolicy::default().max_attempts(0);
+        assert_eq!(policy.attempts(), 1);
+        assert!(policy.retry_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!policy.retry_status(StatusCode::UNAUTHORIZED));
+    }
+}
@@ -0,0 +1,305 @@
+//! Parser for server sent events, used by the streaming endpoints.
+//!
+//! Follows the event stream interpretation rules of the HTML specification: lines may
+//! end with CRLF, LF or CR, lines starting with a colon are comments, a single space
+//! after the field colon is removed and an event is dispatched on an empty line.
+use std::collections::VecDeque;
+
+use futures::{Stream, StreamExt};
+use reqwest::Response;
+
+use crate::Result;
+
+/// A single event received from the server.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Event {
+    /// The event type, `message` unless set by the server.
+    pub event: String,
+    /// The data lines of the event joined with a line feed.
+    pub data: String,
+    /// The last event id set by the server.
+    pub id: Option<String>,
+    /// The reconnection time in milliseconds, if set by the server.
+    pub retry: Option<u64>,
+}
+
+/// Incremental parser that turns chunks of bytes into events.
+#[derive(Debug, Default)]
+pub(crate) struct Parser {
+    line: Vec<u8>,
+    started: bool,
+    skip_lf: bool,
+    data: String,
+    has_data: bool,
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<u64>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a chunk of bytes and returns the events that were completed by it.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let mut bytes = chunk;
+
+        if !self.started && !bytes.is_empty() {
+            self.line.extend_from_slice(bytes);
+            if self.line.len() < 3 && b"\xEF\xBB\xBF".starts_with(&self.line) {
+                // Wait for more bytes to know if the stream starts with a BOM.
+                return events;
+            }
+            self.started = true;
+            let buffered = std::mem::take(&mut self.line);
+            let start = if buffered.starts_with(b"\xEF\xBB\xBF") { 3 } else { 0 };
+            events.extend(self.feed(&buffered[start..]));
+            return events;
+        }
+
+        while let Some(&b) = bytes.first() {
+            if self.skip_lf {
+                self.skip_lf = false;
+                if b == b'\n' {
+                    bytes = &bytes[1..];
+                    continue;
+                }
+            }
+
+            match bytes.iter().position(|&x| x == b'\n' || x == b'\r') {
+                Some(i) => {
+                    self.line.extend_from_slice(&bytes[..i]);
+                    self.skip_lf = bytes[i] == b'\r';
+                    bytes = &bytes[i + 1..];
+
+                    let line = std::mem::take(&mut self.line);
+                    if let Some(evt) = self.process_line(&line) {
+                        events.push(evt);
+                    }
+                }
+                None => {
+                    self.line.extend_from_slice(bytes);
+                    break;
+                }
+            }
+        }
+
+        events
+    }
+
+    fn process_line(&mut self, line: &[u8]) -> Option<Event> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+
+        if line[0] == b':' {
+            // Comment line.
+            return None;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let (field, value) = match line.find(':') {
+            Some(i) => {
+                let value = &line[i + 1..];
+                (&line[..i], value.strip_prefix(' ').unwrap_or(value))
+            }
+            None => (line.as_ref(), ""),
+        };
+
+        match field {
+            "data" => {
+                if self.has_data {
+                    self.data.push('\n');
+                }
+                self.data.push_str(value);
+                self.has_data = true;
+            }
+            "event" => self.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.retry = value.parse().ok();
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<Event> {
+        let event = self.event.take();
+
+        if !self.has_data {
+            self.data.clear();
+            return None;
+        }
+
+        self.has_data = false;
+
+        Some(Event {
+            event: event.unwrap_or_else(|| "message".to_string()),
+            data: std::mem::take(&mut self.data),
+            id: self.id.clone(),
+            retry: self.retry,
+        })
+    }
+}
+
+/// Returns the events sent in the body of the response. An event that is not
+/// terminated by an empty line when the body ends is discarded.
+pub(crate) fn events(resp: Response) -> impl Stream<Item = Result<Event>> + Send {
+    let state = (resp.bytes_stream(), Parser::new(), VecDeque::new());
+
+    futures::stream::unfold(Some(state), |state| async move {
+        let (mut body, mut parser, mut pending) = state?;
+
+        loop {
+            if let Some(evt) = pending.pop_front() {
+                return Some((Ok(evt), Some((body, parser, pending))));
+            }
+
+            match body.next().await {
+                Some(Ok(chunk)) => pending.extend(parser.feed(&chunk)),
+                Some(Err(e)) => return Some((Err(e.into()), None)),
+                None => return None,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &[u8]) -> Vec<Event> {
+        Parser::new().feed(input)
+    }
+
+    fn message(data: &str) -> Event {
+        Event {
+            event: "message".to_string(),
+            data: data.to_string(),
+            id: None,
+            retry: None,
+        }
+    }
+
+    #[test]
+    fn single_event() {
+        assert_eq!(parse(b"data: hello\n\n"), vec![message("hello")]);
+    }
+
+    #[test]
+    fn multi_line_data() {
+        assert_eq!(
+            parse(b"data: first\ndata:second\ndata:  third\n\n"),
+            vec![message("first\nsecond\n third")]
+        );
+    }
+
+    #[test]
+    fn empty_data_lines() {
+        assert_eq!(parse(b"data\ndata\n\n"), vec![message("\n")]);
+        assert_eq!(parse(b"data:\n\n"), vec![message("")]);
+    }
+
+    #[test]
+    fn line_endings() {
+        let expected = vec![message("one"), message("two"), message("three")];
+
+        assert_eq!(parse(b"data: one\r\n\r\ndata: two\r\rdata: three\n\n"), expected);
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        assert_eq!(
+            parse(b": keep-alive\n\n:another\ndata: hello\n: inside\n\n"),
+            vec![message("hello")]
+        );
+    }
+
+    #[test]
+    fn event_id_and_retry() {
+        let events = parse(b"event: update\nid: 42\nretry: 3000\ndata: {}\n\ndata: next\n\n");
+
+        assert_eq!(
+            events,
+            vec![
+                Event {
+                    event: "update".to_string(),
+                    data: "{}".to_string(),
+                    id: Some("42".to_string()),
+                    retry: Some(3000),
+                },
+                Event {
+                    event: "message".to_string(),
+                    data: "next".to_string(),
+                    id: Some("42".to_string()),
+                    retry: Some(3000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_fields_are_ignored() {
+        let events = parse(b"retry: soon\nid: a\0b\nunknown: x\ndata: hello\n\n");
+
+        assert_eq!(events, vec![message("hello")]);
+    }
+
+    #[test]
+    fn event_without_data_is_not_dispatched() {
+        assert_eq!(parse(b"event: ping\n\ndata: hello\n\n"), vec![message("hello")]);
+    }
+
+    #[test]
+    fn byte_order_mark() {
+        assert_eq!(parse(b"\xEF\xBB\xBFdata: hello\n\n"), vec![message("hello")]);
+    }
+
+    #[test]
+    fn incomplete_event_is_not_dispatched() {
+        assert_eq!(parse(b"data: hello\n\ndata: partial\n"), vec![message("hello")]);
+    }
+
+    #[test]
+    fn split_chunks() {
+        let input: &[u8] =
+            b"\xEF\xBB\xBFdata: {\"a\":1}\r\n\r\n: comment\r\nevent: x\r\ndata: line1\r\ndata: line2\r\n\r\ndata: [DONE]\n\n";
+
+        let expected = vec![
+            message("{\"a\":1}"),
+            Event {
+                event: "x".to_string(),
+                data: "line1\nline2".to_string(),
+                id: None,
+                retry: None,
+            },
+            message("[DONE]"),
+        ];
+
+        // Feed the input one byte at a time and in chunks of every size.
+        for size in 1..input.len() {
+            let mut parser = Parser::new();
+            let events: Vec<Event> = input
+                .chunks(size)
+                .flat_map(|chunk| parser.feed(chunk))
+                .collect();
+
+            assert_eq!(events, expected, "chunk size {}", size);
+        }
+    }
+
+    #[test]
+    fn utf8_split_across_chunks() {
+        let input = "data: héllo wörld\n\n".as_bytes();
+
+        let mut parser = Parser::new();
+        let mut events = parser.feed(&input[..8]);
+        events.extend(parser.feed(&input[8..]));
+
+        assert_eq!(events, vec![message("héllo wörld")]);
+    }
+}
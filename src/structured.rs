@@ -0,0 +1,290 @@
+//! Helpers to parse chat responses into typed values, used by
+//! [`Client::generate_structured`](crate::client::Client::generate_structured).
+//!
+//! The JSON schema of the requested type is added to the request, both as a system
+//! instruction and, unless disabled, as the `response_format` of the request. The JSON is
+//! extracted from the response, including from markdown code fences, and parsed into the
+//! type. When parsing fails the model is asked again with the error, up to the maximum
+//! number of retries.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use prediction_guard::{chat, client, structured::JsonSchema};
+//! use serde::Deserialize;
+//!
+//! #[derive(Debug, Deserialize, JsonSchema)]
+//! struct Weather {
+//!     city: String,
+//!     temperature: f64,
+//! }
+//!
+//! let req = chat::Request::<chat::Message>::new("Hermes-3-Llama-3.1-70B".to_string())
+//!     .add_message(chat::Roles::User, "The weather in Paris is 21 degrees.".to_string());
+//!
+//! let weather: Weather = clt.generate_structured(&req).await?;
+//! ```
+use serde::de::DeserializeOwned;
+
+use crate::chat;
+
+pub use schemars::JsonSchema;
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// Options for a structured output request.
+#[derive(Debug, Clone)]
+pub struct Options {
+    max_retries: u32,
+    response_format: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            response_format: true,
+        }
+    }
+}
+
+impl Options {
+    /// Sets the number of times the model is asked again after an invalid response.
+    /// The default is 2.
+    ///
+    /// ## Arguments
+    ///
+    /// * `retries` - The maximum number of retries.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets whether the schema is sent as the `response_format` of the request. Disable
+    /// it for models that do not support JSON mode, the schema is then only sent in the
+    /// instructions.
+    ///
+    /// ## Arguments
+    ///
+    /// * `enabled` - Determines whether to set the response format.
+    pub fn response_format(mut self, enabled: bool) -> Self {
+        self.response_format = enabled;
+        self
+    }
+
+    /// Returns the maximum number of requests made to the model.
+    pub fn attempts(&self) -> u32 {
+        self.max_retries.saturating_add(1)
+    }
+
+    pub(crate) fn uses_response_format(&self) -> bool {
+        self.response_format
+    }
+}
+
+/// Returns the name and the JSON schema of the type. The name only contains the
+/// characters allowed by the `response_format` field.
+pub(crate) fn schema<T: JsonSchema>() -> (String, serde_json::Value) {
+    let name: String = T::schema_name()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(64)
+        .collect();
+
+    let schema = schemars::schema_for!(T).to_value();
+
+    (name, schema)
+}
+
+/// Adds the instructions to answer with JSON matching the schema to the messages. The
+/// instructions are appended to the first system message or sent as a new one.
+pub(crate) fn add_instructions(messages: &mut Vec<chat::Message>, schema: &serde_json::Value) {
+    let schema = serde_json::to_string_pretty(schema).unwrap_or_else(|_| schema.to_string());
+    let instructions = format!(
+        "Respond only with a JSON value that matches the following JSON schema, without any other text:\n{}",
+        schema
+    );
+
+    match messages.first_mut() {
+        Some(m) if m.role == chat::Roles::System => {
+            m.content.push_str("\n\n");
+            m.content.push_str(&instructions);
+        }
+        _ => messages.insert(0, chat::Message::new(chat::Roles::System, instructions)),
+    }
+}
+
+/// Returns the prompt that asks the model to fix an invalid response.
+pub(crate) fn retry_prompt(error: &str) -> String {
+    format!(
+        "Your previous response is not valid: {}. Respond again with only a JSON value that matches the schema.",
+        error
+    )
+}
+
+/// Extracts the JSON from the content of a response.
+///
+/// The content of the first markdown code fence is used if there is one. The JSON value
+/// starts at the first `{` or `[` and ends at the matching bracket, any text around it
+/// is ignored. If there is no JSON value the trimmed content is returned.
+///
+/// ## Arguments
+///
+/// * `content` - The content of the response.
+pub fn extract_json(content: &str) -> &str {
+    let text = fenced(content).unwrap_or(content);
+
+    let start = match text.find(['{', '[']) {
+        Some(i) => i,
+        None => return text.trim(),
+    };
+
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (i, c) in text[start..].char_indices() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &text[start..start + i + 1];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    text[start..].trim()
+}
+
+/// Returns the content of the first markdown code fence.
+fn fenced(content: &str) -> Option<&str> {
+    let open = content.find("```")?;
+    let rest = &content[open + 3..];
+
+    // Skip the language of the fence, e.g. ```json
+    let body = if rest.trim_start_matches([' ', '\t']).starts_with(['{', '[']) {
+        rest
+    } else {
+        &rest[rest.find('\n').map(|i| i + 1).unwrap_or(rest.len())..]
+    };
+
+    let end = body.find("```").unwrap_or(body.len());
+    Some(&body[..end])
+}
+
+/// Parses the content of a response into the type. Returns the parse error as text so
+/// it can be sent back to the model.
+pub(crate) fn parse<T: DeserializeOwned>(content: &str) -> std::result::Result<T, String> {
+    let json = extract_json(content);
+
+    if json.is_empty() {
+        return Err("the response is empty".to_string());
+    }
+
+    serde_json::from_str(json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Weather {
+        city: String,
+        temperature: f64,
+    }
+
+    #[test]
+    fn json_extraction() {
+        let cases = [
+            (r#"{"a":1}"#, r#"{"a":1}"#),
+            ("  [1, 2]\n", "[1, 2]"),
+            ("```json\n{\"a\":1}\n```", "{\"a\":1}"),
+            (
+                "Here it is:\n```\n[{\"a\":\"}\"}]\n```\nDone.",
+                "[{\"a\":\"}\"}]",
+            ),
+            ("```{\"a\":1}```", "{\"a\":1}"),
+            (
+                "The answer is {\"a\":{\"b\":\"x\\\"}\"}} as requested.",
+                "{\"a\":{\"b\":\"x\\\"}\"}}",
+            ),
+            ("no json here", "no json here"),
+            ("{\"a\":", "{\"a\":"),
+        ];
+
+        for (content, expected) in cases {
+            assert_eq!(extract_json(content), expected, "{}", content);
+        }
+    }
+
+    #[test]
+    fn parse_response() {
+        let weather: Weather =
+            parse("```json\n{\"city\":\"Paris\",\"temperature\":21.5}\n```").expect("weather");
+        assert_eq!(
+            weather,
+            Weather {
+                city: "Paris".to_string(),
+                temperature: 21.5
+            }
+        );
+
+        let err = parse::<Weather>(r#"{"city":"Paris"}"#).expect_err("missing field");
+        assert!(err.contains("temperature"), "{}", err);
+
+        assert!(parse::<Weather>("   ").is_err());
+    }
+
+    #[test]
+    fn schema_instructions() {
+        let (name, schema) = schema::<Weather>();
+        assert_eq!(name, "Weather");
+        assert_eq!(schema["properties"]["city"]["type"], "string");
+
+        let mut messages = vec![chat::Message::new(
+            chat::Roles::User,
+            "Paris is 21 degrees".to_string(),
+        )];
+        add_instructions(&mut messages, &schema);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].role, chat::Roles::System);
+        assert!(messages[0].content.text().contains("\"temperature\""));
+
+        let mut messages = vec![chat::Message::new(
+            chat::Roles::System,
+            "You are a weather bot.".to_string(),
+        )];
+        add_instructions(&mut messages, &schema);
+
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0]
+            .content
+            .text()
+            .starts_with("You are a weather bot.\n\n"));
+    }
+}
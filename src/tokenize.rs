@@ -4,6 +4,12 @@ use serde::{Deserialize, Serialize};
 /// Path to the tokenize endpoint.
 pub const PATH: &str = "/tokenize";
 
+/// The number of characters per token used to estimate the tokens of a text.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// The number of tokens added to every chat message for the role and the chat template.
+pub(crate) const MESSAGE_OVERHEAD: usize = 4;
+
 /// Request type for the tokenize endpoint.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Request {
@@ -43,3 +49,26 @@ pub struct Response {
     pub model: String,
     pub tokens: Vec<Tokens>,
 }
+
+/// Estimates the number of tokens of a text without calling the tokenize endpoint.
+pub(crate) fn estimate(text: &str) -> usize {
+    estimate_chars(text.chars().count())
+}
+
+/// Estimates the number of tokens of a text with the number of characters.
+pub(crate) fn estimate_chars(chars: usize) -> usize {
+    chars.div_ceil(CHARS_PER_TOKEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_estimate() {
+        assert_eq!(estimate(""), 0);
+        assert_eq!(estimate("abcd"), 1);
+        assert_eq!(estimate("abcde"), 2);
+        assert_eq!(estimate("héllo wörld!"), 3);
+    }
+}
@@ -225,10 +225,7 @@ fn deserialize_language<'de, D>(deserializer: D) -> Result<Language, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let lang: &str = match Deserialize::deserialize(deserializer) {
-        Ok(l) => l,
-        Err(e) => return Err(e),
-    };
+    let lang: &str = Deserialize::deserialize(deserializer)?;
 
     match lang {
         "afr" => Ok(Language::Afrikanns),